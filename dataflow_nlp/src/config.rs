@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use dataflow::pipeline::{check_files_exist, NoParams, NodeRegistry};
+use serde::Deserialize;
+
+use crate::{
+    pipelines::RandomLoader,
+    tokenization::{
+        AlphabetTokenizer, BPETokenizer, SentenceTokenizer, Tokenizer, WhitespaceTokenizer,
+        WordpieceTokenizer,
+    },
+    vocab::{BPEVocab, Vocab, WordPieceVocab},
+};
+
+/// Register the tokenizers, vocabs and loaders from this crate, so config files can use them
+pub fn register_nodes(registry: NodeRegistry) -> NodeRegistry {
+    registry
+        .try_register::<Vec<()>, _, _, _>("RandomLoader", |p: RandomLoaderParams| {
+            let loader = match p.files {
+                RandomLoaderFiles::Files { files } => {
+                    check_files_exist(&files)?;
+                    RandomLoader::new(&files)
+                }
+                RandomLoaderFiles::Directory { directory } => {
+                    RandomLoader::try_from_directory(&directory)
+                        .map_err(|e| format!("can't read {}: {e}", directory.display()))?
+                }
+            };
+            let loader = loader.with_delimeter(p.delimiter);
+            let loader = match p.min_index {
+                Some(min_index) => loader.min_index(min_index),
+                None => loader,
+            };
+            Ok::<_, String>(match p.max_index {
+                Some(max_index) => loader.max_index(max_index),
+                None => loader,
+            })
+        })
+        // Tokenizers take a single string or a batch of strings
+        .register::<String, _, _>("WhitespaceTokenizer", |_: NoParams| {
+            WhitespaceTokenizer::load()
+        })
+        .register::<Vec<String>, _, _>("WhitespaceTokenizer", |_: NoParams| {
+            WhitespaceTokenizer::load()
+        })
+        .register::<String, _, _>("AlphabetTokenizer", |_: NoParams| AlphabetTokenizer::load())
+        .register::<Vec<String>, _, _>("AlphabetTokenizer", |_: NoParams| AlphabetTokenizer::load())
+        .register::<String, _, _>("SentenceTokenizer", |p: SentenceParams| {
+            SentenceTokenizer::new(p.keep_punctuation)
+        })
+        .register::<Vec<String>, _, _>("SentenceTokenizer", |p: SentenceParams| {
+            SentenceTokenizer::new(p.keep_punctuation)
+        })
+        .register::<String, _, _>("WordpieceTokenizer", |_: NoParams| {
+            WordpieceTokenizer::load()
+        })
+        .register::<Vec<String>, _, _>("WordpieceTokenizer", |_: NoParams| {
+            WordpieceTokenizer::load()
+        })
+        .register::<String, _, _>("BPETokenizer", |_: NoParams| BPETokenizer::load())
+        .register::<Vec<String>, _, _>("BPETokenizer", |_: NoParams| BPETokenizer::load())
+        // Vocabs map tokens to indexes, or indexes back to tokens
+        .register::<Vec<String>, _, _>("WordPieceVocab", |_: NoParams| WordPieceVocab::load())
+        .register::<Vec<Vec<String>>, _, _>("WordPieceVocab", |_: NoParams| WordPieceVocab::load())
+        .register::<Vec<usize>, _, _>("WordPieceVocab", |_: NoParams| WordPieceVocab::load())
+        .register::<Vec<Vec<usize>>, _, _>("WordPieceVocab", |_: NoParams| WordPieceVocab::load())
+        .register::<Vec<String>, _, _>("BPEVocab", |_: NoParams| BPEVocab::load())
+        .register::<Vec<Vec<String>>, _, _>("BPEVocab", |_: NoParams| BPEVocab::load())
+        .register::<Vec<usize>, _, _>("BPEVocab", |_: NoParams| BPEVocab::load())
+        .register::<Vec<Vec<usize>>, _, _>("BPEVocab", |_: NoParams| BPEVocab::load())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, untagged)]
+enum RandomLoaderFiles {
+    Files { files: Vec<String> },
+    Directory { directory: PathBuf },
+}
+
+#[derive(Deserialize)]
+struct RandomLoaderParams {
+    #[serde(flatten)]
+    files: RandomLoaderFiles,
+    #[serde(default = "default_delimiter")]
+    delimiter: String,
+    min_index: Option<usize>,
+    max_index: Option<usize>,
+}
+
+fn default_delimiter() -> String {
+    "\n".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SentenceParams {
+    #[serde(default)]
+    keep_punctuation: bool,
+}
@@ -1,5 +1,8 @@
 /// Uilities for dealing with batches, such as shuffling and sorting batches
 pub mod batching;
+/// Registers this crate's nodes for config driven pipelines
+#[cfg(feature = "config")]
+pub mod config;
 /// Dataflow pipeline nodes
 pub mod pipelines;
 /// All tokenization and untokenization
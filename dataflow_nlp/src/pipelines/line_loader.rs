@@ -30,18 +30,15 @@ impl RandomLoader {
 
     /// Create a new RandomLoader with all files in a directory
     pub fn from_directory<T: AsRef<Path>>(path: T) -> Self {
-        let files = std::fs::read_dir(path)
-            .unwrap()
-            .map(|r| r.unwrap().path().to_str().unwrap().to_string())
-            .collect();
-        RandomLoader {
-            files,
-            delimeter: "\n".to_string(),
-            total_examples: 0,
-            currently_loaded_index: 0,
-            min_index: 0,
-            max_index: usize::MAX,
-        }
+        Self::try_from_directory(path).unwrap()
+    }
+
+    /// Create a new RandomLoader with all files in a directory, failing if the directory can't be read
+    pub fn try_from_directory<T: AsRef<Path>>(path: T) -> std::io::Result<Self> {
+        let files = std::fs::read_dir(path)?
+            .map(|r| r.map(|f| f.path().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(&files))
     }
 
     pub fn with_delimeter(self, delimeter: String) -> Self {
@@ -69,7 +66,7 @@ fn load_text_segments(
     let mut segments = Vec::new();
     let mut current_segment = String::new();
 
-    for line in reader.lines().flatten() {
+    for line in reader.lines().map_while(Result::ok) {
         if line.contains(delimiter) {
             let mut line_segments = line.split(delimiter);
 
@@ -139,7 +136,7 @@ impl Node<Vec<()>> for RandomLoader {
         loaded
     }
 
-    fn reset(&mut self) {
+    fn reset(&mut self, _epoch: Epoch) {
         // Count the total number of examples
         self.total_examples = 0;
         for file in &self.files {
@@ -150,7 +147,7 @@ impl Node<Vec<()>> for RandomLoader {
             } else {
                 delimeter_count += reader
                     .lines()
-                    .flatten()
+                    .map_while(Result::ok)
                     .map(|line| line.matches(&self.delimeter).count())
                     .sum::<usize>();
                 delimeter_count += 1; // Since delimeters divide the examples, there should be 1 more example than delimeter
@@ -167,4 +164,18 @@ impl Node<Vec<()>> for RandomLoader {
     fn data_remaining(&self, _before: usize) -> usize {
         self.total_examples - (self.currently_loaded_index - self.min_index)
     }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.currently_loaded_index)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.currently_loaded_index = state.index();
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("RandomLoader")
+            .param("files", self.files.len())
+            .param("delimiter", self.delimeter.escape_default())
+    }
 }
@@ -0,0 +1,37 @@
+#[cfg(feature = "config")]
+#[test]
+fn test_config_tokenization() {
+    use dataflow::pipeline::*;
+
+    let registry = crate::config::register_nodes(NodeRegistry::premade());
+    let config: PipelineConfig = serde_json::from_str(
+        r#"{"stages": [
+            {"node": "VecLoader", "params": {"elements": ["hello there", "general"]}},
+            {"node": "WordpieceTokenizer"},
+            {"node": "WordPieceVocab"}
+        ]}"#,
+    )
+    .unwrap();
+    let pipeline = registry.build::<Vec<()>, Vec<Vec<usize>>>(&config).unwrap();
+    assert_eq!(pipeline.run(10).len(), 2);
+
+    // Tokenizers can't take indexes
+    let mut config = config;
+    config
+        .stages
+        .push(serde_json::from_str(r#"{"node": "SentenceTokenizer"}"#).unwrap());
+    assert!(matches!(
+        registry.build::<Vec<()>, Vec<Vec<String>>>(&config),
+        Err(ConfigError::TypeMismatch { stage: 3, .. })
+    ));
+
+    // A missing directory fails the build instead of panicking
+    let config: PipelineConfig = serde_json::from_str(
+        r#"{"stages": [{"node": "RandomLoader", "params": {"directory": "missing_directory"}}]}"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        registry.build::<Vec<()>, Vec<String>>(&config),
+        Err(ConfigError::Params { stage: 0, .. })
+    ));
+}
@@ -49,8 +49,8 @@ impl Tokenizer for BPETokenizer {
             if line.contains(' ') && !line.contains('#') {
                 let line: Vec<&str> = line.split(' ').collect();
                 // Make sure vocab contains both tokens and combined token
-                if token2index.contains_key(&line[0].to_string())
-                    && token2index.contains_key(&line[1].to_string())
+                if token2index.contains_key(line[0])
+                    && token2index.contains_key(line[1])
                     && token2index.contains_key(&format!("{}{}", line[0], line[1]))
                 {
                     merges.push((line[0].to_string(), line[1].to_string()));
@@ -3,7 +3,7 @@ use super::*;
 
 #[test]
 fn tokenize_alphabet() {
-    let letters: Vec<String> = vec!["h", "e", "l", "l", "o"]
+    let letters: Vec<String> = ["h", "e", "l", "l", "o"]
         .iter()
         .map(|t| (*t).to_string())
         .collect();
@@ -13,7 +13,7 @@ fn tokenize_alphabet() {
 
 #[test]
 fn tokenize_spaces() {
-    let tokens: Vec<String> = vec!["hello", "how", "are", "you"]
+    let tokens: Vec<String> = ["hello", "how", "are", "you"]
         .iter()
         .map(|t| (*t).to_string())
         .collect();
@@ -23,7 +23,7 @@ fn tokenize_spaces() {
 
 #[test]
 fn tokenize_sentences() {
-    let tokens: Vec<String> = vec!["hello, how are you?", "good, how are you?"]
+    let tokens: Vec<String> = ["hello, how are you?", "good, how are you?"]
         .iter()
         .map(|t| (*t).to_string())
         .collect();
@@ -36,7 +36,7 @@ fn tokenize_sentences() {
 
 #[test]
 fn tokenize_bpe() {
-    let tokens: Vec<String> = vec!["hello", ",", " ", "how", " ", "are", " ", "you"]
+    let tokens: Vec<String> = ["hello", ",", " ", "how", " ", "are", " ", "you"]
         .iter()
         .map(|str| str.to_string())
         .collect();
@@ -50,7 +50,7 @@ fn tokenize_bpe() {
 
 #[test]
 fn tokenize_wordpiece() {
-    let tokens: Vec<String> = vec!["hello", ",", "how", "are", "you"]
+    let tokens: Vec<String> = ["hello", ",", "how", "are", "you"]
         .iter()
         .map(|str| str.to_string())
         .collect();
@@ -30,7 +30,7 @@ impl Tokenizer for WordpieceTokenizer {
             .expect("WordPiece Tokenizer failed to build!");
 
         let mut tokenizer = HFTokenizer::new(wordpiece);
-        tokenizer.with_pre_tokenizer(Whitespace::default());
+        tokenizer.with_pre_tokenizer(Whitespace);
         WordpieceTokenizer {
             hf_tokenizer: tokenizer,
         }
@@ -82,6 +82,7 @@ impl Tokenizer for WordpieceTokenizer {
         untokenized_string
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn batch_untokenize(&self, tokens: Vec<Vec<String>>) -> Vec<String> {
         let mut untokenized_strings = vec![String::new(); tokens.len()];
         for i in 0..tokens.len() {
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use super::{BasicVocab, TokenNotFoundError, Vocab};
-use dataflow::prelude::Node;
+use dataflow::prelude::{Node, TryNode};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Default, Clone)]
@@ -95,3 +95,39 @@ impl Node<Vec<usize>> for BPEVocab {
         self.tokens_from_indexes(&input).unwrap()
     }
 }
+
+impl TryNode<Vec<Vec<String>>> for BPEVocab {
+    type Output = Vec<Vec<usize>>;
+    type Error = TokenNotFoundError;
+
+    fn try_process(&mut self, input: Vec<Vec<String>>) -> Result<Self::Output, Self::Error> {
+        self.batch_indexes_from_tokens(&input)
+    }
+}
+
+impl TryNode<Vec<String>> for BPEVocab {
+    type Output = Vec<usize>;
+    type Error = TokenNotFoundError;
+
+    fn try_process(&mut self, input: Vec<String>) -> Result<Self::Output, Self::Error> {
+        self.indexes_from_tokens(&input)
+    }
+}
+
+impl TryNode<Vec<Vec<usize>>> for BPEVocab {
+    type Output = Vec<Vec<String>>;
+    type Error = TokenNotFoundError;
+
+    fn try_process(&mut self, input: Vec<Vec<usize>>) -> Result<Self::Output, Self::Error> {
+        self.batch_tokens_from_indexes(&input)
+    }
+}
+
+impl TryNode<Vec<usize>> for BPEVocab {
+    type Output = Vec<String>;
+    type Error = TokenNotFoundError;
+
+    fn try_process(&mut self, input: Vec<usize>) -> Result<Self::Output, Self::Error> {
+        self.tokens_from_indexes(&input)
+    }
+}
@@ -34,3 +34,11 @@ pub trait Vocab: Serialize + Default + Clone {
 /// Custom Error Types
 #[derive(Debug)]
 pub struct TokenNotFoundError;
+
+impl std::fmt::Display for TokenNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Token not found in vocab")
+    }
+}
+
+impl std::error::Error for TokenNotFoundError {}
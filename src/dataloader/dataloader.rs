@@ -1,124 +1,664 @@
-use rand::{prelude::SliceRandom, thread_rng};
-use std::{collections::VecDeque, thread};
+use rand::prelude::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    task::Waker,
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::pipeline::Node;
+use crate::pipeline::{Epoch, Fallible, Node, NodeState, Profile, SizeHint, StageStats, TryNode};
 
+/// A block taken out of the source, which runs it through a worker's stages when called with the block's epoch
+type Load<T> = Box<dyn FnOnce(Epoch) -> Vec<T> + Send>;
+
+/// The pipeline as the workers see it: a source they take turns on, and stages each worker runs on its own copy
+trait Pipeline<T>: Send {
+    fn reset(&mut self, epoch: Epoch);
+    /// The estimate and bounds of the samples left, counting examples taken out of the source that are still in the stages
+    fn size(&self, in_flight: usize) -> (usize, SizeHint);
+    fn is_empty(&self) -> bool;
+    fn state_dict(&self) -> NodeState;
+    fn load_state_dict(&mut self, state: &NodeState);
+    /// Take a block out of the source for a worker. Returns how many examples it took, and the rest of the loading to do outside the lock
+    fn take(&mut self, load_block_size: usize, worker: usize) -> (usize, Load<T>);
+    fn profiles(&self) -> Vec<Profile>;
+}
+
+struct Split<N, St> {
+    source: N,
+    stages: St,
+    copies: Vec<Arc<Mutex<St>>>, // Each worker's own stages, only ever locked by that worker
+}
+
+impl<S: Send + 'static, T, N, St> Pipeline<T> for Split<N, St>
+where
+    N: Node<Vec<()>, Output = Vec<S>> + Send,
+    St: Node<Vec<S>, Output = Vec<T>> + Clone + Send + 'static,
+{
+    fn reset(&mut self, epoch: Epoch) {
+        self.source.reset(epoch);
+    }
+
+    fn size(&self, in_flight: usize) -> (usize, SizeHint) {
+        (
+            self.stages
+                .data_remaining(self.source.data_remaining(0).saturating_add(in_flight)),
+            self.stages
+                .size_hint(self.source.size_hint(SizeHint::exact(0)) + SizeHint::exact(in_flight)),
+        )
+    }
+
+    fn is_empty(&self) -> bool {
+        self.source.size_hint(SizeHint::exact(0)).is_empty()
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.source.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.source.load_state_dict(state);
+    }
+
+    fn take(&mut self, load_block_size: usize, worker: usize) -> (usize, Load<T>) {
+        while self.copies.len() <= worker {
+            self.copies.push(Arc::new(Mutex::new(self.stages.clone())));
+        }
+        let stages = self.copies[worker].clone();
+        let data = self.source.process(vec![(); load_block_size]);
+        let taken = data.len();
+        let load = move |epoch| {
+            let mut stages = stages.lock().unwrap();
+            stages.reset(epoch);
+            stages.process(data)
+        };
+        (taken, Box::new(load))
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        [self.source.profiles(), self.stages.profiles()].concat()
+    }
+}
+
+/// Messages sent from the loading workers to the dataloader
+enum Message<T> {
+    /// A block was taken out of the source. Sent in block order, before the block is loaded
+    Block {
+        index: usize,
+        state: NodeState, // Source state before the block was taken
+        skipped: usize,   // Samples not sent because they were delivered before a restore
+    },
+    /// A block went through the stages. Sent before any of its samples
+    Loaded { index: usize, size: usize },
+    /// A sample and the block it came from
+    Sample(T, usize),
+    /// The worker ran out of data for this epoch
+    Done,
+    /// The pipeline panicked while loading
+    Panic(Box<dyn Any + Send>),
+}
+
+/// State shared between the dataloader and its workers
+struct Shared<T> {
+    pipeline: Mutex<Box<dyn Pipeline<T>>>,
+    pending: AtomicUsize, // Samples loaded that haven't been returned yet
+    in_flight: AtomicUsize, // Examples taken out of the source that are still going through the stages
+    blocks_loaded: AtomicUsize, // Blocks loaded this epoch, used to seed each block's shuffle
+    waker: Mutex<Option<Waker>>, // Waker of an async task waiting on the queue
+    skip: Mutex<BTreeMap<usize, usize>>, // Samples to skip per block, delivered before a restore
+    queued: AtomicUsize,  // Samples sent to the queue and not received yet
+}
+
+/// Delivery progress of a block loaded this epoch
+struct BlockProgress {
+    index: usize,
+    state: NodeState,
+    size: usize,
+    delivered: usize,
+}
+
+/// A snapshot of the dataloader's throughput and the stats of profiled stages in its pipeline
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataloaderMetrics {
+    /// Samples returned since loading started
+    pub samples: usize,
+    /// Samples returned per second since loading started
+    pub samples_per_sec: f64,
+    /// Time the iterator spent waiting on the workers for a sample
+    pub blocked: Duration,
+    /// Samples waiting in the queue
+    pub buffered: usize,
+    pub buffer_size: usize,
+    pub stages: Vec<StageStats>,
+}
+
+/// A position in the dataloader, which can be saved and restored to resume mid-epoch
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub epoch: Epoch,
+    /// The first block that wasn't fully delivered
+    pub block: usize,
+    /// The pipeline's state before that block was loaded
+    pub pipeline: NodeState,
+    /// How many samples were already delivered from each block, starting at `block`
+    pub delivered: Vec<usize>,
+}
+
+struct Worker {
+    start: Sender<Epoch>,
+    handle: thread::JoinHandle<()>,
+}
+
+/// Runs a pipeline on a set of persistent worker threads, which push loaded samples into a bounded queue.
+///
+/// Workers share the source, so only one of them takes a block out of it at a time. The stages after the source run
+/// on each worker's own copy outside the lock, so blocks go through them in parallel. Stages are reset with each
+/// block's epoch before it is loaded, and aren't checkpointed, so they shouldn't carry state from one block to the next.
+/// `new` puts the whole pipeline in the source, `parallel` splits off the stages.
+///
+/// All randomness is seeded from the dataloader's seed and the epoch index. With a single worker, the same seed
+/// gives the same sample order every run. With more workers, blocks are deterministic but may interleave differently.
 pub struct Dataloader<T> {
-    pipeline: Option<Box<dyn Node<Vec<()>, Output = Vec<T>> + Send>>,
-    last_pipeline_length: usize,
-    buffer: VecDeque<T>,
+    shared: Arc<Shared<T>>,
+    last_pipeline_size: (usize, SizeHint), // The pipeline's estimate and bounds when it was last free to ask
+    receiver: Option<Receiver<Message<T>>>,
+    workers: Vec<Worker>,
+    running_workers: usize, // Workers that haven't finished the current epoch
+    blocks: VecDeque<BlockProgress>, // Blocks that may still have undelivered samples, the first is where a checkpoint resumes
+    epoch: Epoch,
+    epoch_ended: bool, // The current epoch ran out, the next one starts when more samples are requested
+    epochs: usize,     // Epochs loaded before the iterator ends
+    epochs_run: usize, // Epochs finished since the iterator last ended
+    epoch_end_hooks: Vec<Box<dyn FnMut(Epoch) + Send>>,
     load_block_size: usize,
     buffer_size: usize,
-    #[allow(clippy::type_complexity)]
-    loading_process:
-        Option<thread::JoinHandle<(Box<dyn Node<Vec<()>, Output = Vec<T>> + Send>, Vec<T>)>>,
+    num_workers: usize,
+    profiles: Vec<Profile>,
+    started: Option<Instant>, // When the first sample was requested
+    samples: usize,
+    blocked: Duration,
 }
 
 impl<T: Send + 'static> Dataloader<T> {
-    pub fn new(mut pipeline: impl Node<Vec<()>, Output = Vec<T>> + Send + 'static) -> Self {
-        pipeline.reset();
-        Dataloader {
-            pipeline: Some(Box::new(pipeline)),
-            buffer: VecDeque::new(),
-            last_pipeline_length: 0,
+    /// Load from a pipeline that runs one block at a time
+    pub fn new(pipeline: impl Node<Vec<()>, Output = Vec<T>> + Send + 'static) -> Self {
+        Dataloader::parallel(pipeline, (|x| x) as fn(Vec<T>) -> Vec<T>)
+    }
+
+    /// Load from a source that runs one block at a time, and stages that every worker runs on its own copy in parallel.
+    /// Checkpoints save the source's state
+    pub fn parallel<S: Send + 'static>(
+        source: impl Node<Vec<()>, Output = Vec<S>> + Send + 'static,
+        stages: impl Node<Vec<S>, Output = Vec<T>> + Clone + Send + 'static,
+    ) -> Self {
+        let pipeline = Split {
+            source,
+            stages,
+            copies: vec![],
+        };
+        let profiles = pipeline.profiles();
+        let mut dataloader = Dataloader {
+            shared: Arc::new(Shared {
+                pipeline: Mutex::new(Box::new(pipeline)),
+                pending: AtomicUsize::new(0),
+                in_flight: AtomicUsize::new(0),
+                blocks_loaded: AtomicUsize::new(0),
+                waker: Mutex::new(None),
+                skip: Mutex::new(BTreeMap::new()),
+                queued: AtomicUsize::new(0),
+            }),
+            last_pipeline_size: (0, SizeHint::exact(0)),
+            receiver: None,
+            workers: vec![],
+            running_workers: 0,
+            blocks: VecDeque::new(),
+            epoch: Epoch::new(0, rand::random()),
+            epoch_ended: false,
+            epochs: 1,
+            epochs_run: 0,
+            epoch_end_hooks: vec![],
             load_block_size: 1000,
             buffer_size: 1000,
-            loading_process: None,
+            num_workers: 1,
+            profiles,
+            started: None,
+            samples: 0,
+            blocked: Duration::ZERO,
+        };
+        dataloader.reset_pipeline();
+        dataloader
+    }
+
+    /// Seed all randomness in the pipeline. Should be set before loading
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.epoch = Epoch::new(0, seed);
+        self.epoch_ended = false;
+        self.reset_pipeline();
+        self
+    }
+
+    /// How many samples each worker loads from the pipeline at a time
+    pub fn load_block_size(mut self, load_block_size: usize) -> Self {
+        self.load_block_size = load_block_size;
+        self
+    }
+
+    /// How many loaded samples can wait in the queue (the prefetch depth)
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// How many worker threads load from the pipeline
+    pub fn workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = num_workers.max(1);
+        self
+    }
+
+    /// How many epochs to load before the iterator ends. Defaults to 1
+    pub fn epochs(mut self, epochs: usize) -> Self {
+        self.epochs = epochs.max(1);
+        self
+    }
+
+    /// Call a function with every epoch that runs out, before the next one starts
+    pub fn on_epoch_end(mut self, hook: impl FnMut(Epoch) + Send + 'static) -> Self {
+        self.epoch_end_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// The epoch currently being loaded, or the last one if it ran out
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// Reset the pipeline for the current epoch. The source is child 0 of the dataloader's epoch, block shuffles are child 1 and block stages are child 2
+    fn reset_pipeline(&mut self) {
+        let mut pipeline = self.shared.pipeline.lock().unwrap();
+        pipeline.reset(self.epoch.child(0));
+        self.last_pipeline_size = pipeline.size(0);
+        self.shared.blocks_loaded.store(0, Ordering::SeqCst);
+        self.shared.pending.store(0, Ordering::SeqCst);
+        self.shared.in_flight.store(0, Ordering::SeqCst);
+        self.shared.queued.store(0, Ordering::SeqCst);
+        self.shared.skip.lock().unwrap().clear();
+        // Until the first block arrives, a checkpoint resumes from the start of the epoch
+        self.blocks = VecDeque::from([BlockProgress {
+            index: 0,
+            state: pipeline.state_dict(),
+            size: usize::MAX,
+            delivered: 0,
+        }]);
+    }
+
+    /// A snapshot of throughput, buffer occupancy and profiled stage stats. Can be called while loading
+    pub fn metrics(&self) -> DataloaderMetrics {
+        let elapsed = self.started.map_or(0., |s| s.elapsed().as_secs_f64());
+        DataloaderMetrics {
+            samples: self.samples,
+            samples_per_sec: if elapsed > 0. {
+                self.samples as f64 / elapsed
+            } else {
+                0.
+            },
+            blocked: self.blocked,
+            buffered: self.shared.queued.load(Ordering::SeqCst),
+            buffer_size: self.buffer_size,
+            stages: self.profiles.iter().map(Profile::stats).collect(),
         }
     }
 
-    pub fn load_block_size(self, load_block_size: usize) -> Self {
-        Dataloader {
-            load_block_size,
-            ..self
+    /// Save the current position. Restoring it yields exactly the samples that weren't returned yet
+    pub fn checkpoint(&self) -> Checkpoint {
+        if self.epoch_ended {
+            // Resume at the start of the next epoch, which has no pipeline state to load
+            return Checkpoint {
+                epoch: self.epoch.next(),
+                block: 0,
+                pipeline: NodeState::Empty,
+                delivered: vec![],
+            };
+        }
+        // There is always at least one block
+        let first = &self.blocks[0];
+        Checkpoint {
+            epoch: self.epoch,
+            block: first.index,
+            pipeline: first.state.clone(),
+            delivered: self.blocks.iter().map(|b| b.delivered).collect(),
         }
     }
 
-    pub fn buffer_size(self, buffer_size: usize) -> Self {
-        Dataloader {
-            buffer_size,
-            ..self
+    /// Resume from a checkpoint. The pipeline must be built the same way as the one that was checkpointed
+    pub fn restore(&mut self, checkpoint: &Checkpoint) {
+        self.stop_workers();
+        self.epoch = checkpoint.epoch;
+        self.epoch_ended = false;
+        self.epochs_run = 0;
+        self.reset_pipeline();
+        // A checkpoint taken between epochs starts fresh, with nothing to load
+        if checkpoint.pipeline != NodeState::Empty {
+            let mut pipeline = self.shared.pipeline.lock().unwrap();
+            pipeline.load_state_dict(&checkpoint.pipeline);
+            self.last_pipeline_size = pipeline.size(0);
         }
+        self.shared
+            .blocks_loaded
+            .store(checkpoint.block, Ordering::SeqCst);
+        *self.shared.skip.lock().unwrap() = checkpoint
+            .delivered
+            .iter()
+            .enumerate()
+            .map(|(i, delivered)| (checkpoint.block + i, *delivered))
+            .collect();
+        self.blocks = VecDeque::from([BlockProgress {
+            index: checkpoint.block,
+            state: checkpoint.pipeline.clone(),
+            size: usize::MAX,
+            delivered: checkpoint.delivered.first().copied().unwrap_or(0),
+        }]);
     }
 
-    fn load_block(&mut self) {
-        if self.loading_process.is_some()
-            || self.pipeline.is_none()
-            || self.pipeline.as_ref().unwrap().data_remaining(0) == 0
-        {
-            return;
+    fn spawn_workers(&mut self) {
+        let (sender, receiver) = mpsc::sync_channel(self.buffer_size);
+        self.receiver = Some(receiver);
+        self.workers = (0..self.num_workers)
+            .map(|worker| {
+                let (start, start_receiver) = mpsc::channel::<Epoch>();
+                let (shared, sender) = (self.shared.clone(), sender.clone());
+                let load_block_size = self.load_block_size;
+                let handle = thread::spawn(move || {
+                    // Each start signal runs one epoch
+                    while let Ok(epoch) = start_receiver.recv() {
+                        let message = match panic::catch_unwind(AssertUnwindSafe(|| {
+                            load_epoch(&shared, &sender, epoch, load_block_size, worker)
+                        })) {
+                            Ok(true) => Message::Done,
+                            Ok(false) => return, // Dataloader hung up
+                            Err(payload) => Message::Panic(payload),
+                        };
+                        if !send(&shared, &sender, message) {
+                            return;
+                        }
+                    }
+                });
+                Worker { start, handle }
+            })
+            .collect();
+    }
+
+    /// Start loading the current epoch if it isn't running, moving on to the next epoch if the current one ran out
+    fn start_epoch(&mut self) {
+        self.started.get_or_insert_with(Instant::now);
+        if self.workers.is_empty() {
+            self.spawn_workers();
+        }
+        if self.epoch_ended {
+            self.epoch = self.epoch.next();
+            self.epoch_ended = false;
+            self.reset_pipeline();
+        }
+        if self.running_workers == 0 {
+            let epoch = self.epoch;
+            self.running_workers = self
+                .workers
+                .iter()
+                .filter(|w| w.start.send(epoch).is_ok())
+                .count();
         }
+    }
 
-        // Launch loading thread
-        let mut pipeline = self.pipeline.take().unwrap();
-        let load_block_size = self.load_block_size;
-        self.loading_process = Some(thread::spawn(move || {
-            let mut data = pipeline.process(vec![(); load_block_size]);
-            data.shuffle(&mut thread_rng());
-            (pipeline, data)
-        }));
+    /// Handle a message from the workers. Returns the item to yield, if any
+    fn receive(&mut self, message: Message<T>) -> Option<Option<T>> {
+        match message {
+            Message::Block {
+                index,
+                state,
+                skipped,
+            } => {
+                let progress = BlockProgress {
+                    index,
+                    state,
+                    size: usize::MAX, // Known once the block is loaded
+                    delivered: skipped,
+                };
+                match self.blocks.back_mut() {
+                    Some(last) if last.index == index => *last = progress,
+                    _ => self.blocks.push_back(progress),
+                }
+                None
+            }
+            Message::Loaded { index, size } => {
+                if let Some(progress) = self.blocks.iter_mut().find(|b| b.index == index) {
+                    progress.size = size;
+                }
+                None
+            }
+            Message::Sample(sample, block) => {
+                self.shared.pending.fetch_sub(1, Ordering::SeqCst);
+                self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+                self.samples += 1;
+                if let Some(progress) = self.blocks.iter_mut().find(|b| b.index == block) {
+                    progress.delivered += 1;
+                }
+                while self.blocks.len() > 1 && self.blocks[0].delivered >= self.blocks[0].size {
+                    self.blocks.pop_front();
+                }
+                Some(Some(sample))
+            }
+            Message::Done => {
+                self.running_workers -= 1;
+                if self.running_workers > 0 {
+                    return None;
+                }
+                self.epoch_ended = true;
+                for hook in &mut self.epoch_end_hooks {
+                    hook(self.epoch);
+                }
+                self.epochs_run += 1;
+                if self.epochs_run < self.epochs {
+                    self.start_epoch();
+                    return None;
+                }
+                self.epochs_run = 0;
+                Some(None)
+            }
+            Message::Panic(payload) => panic::resume_unwind(payload),
+        }
     }
 
+    /// An estimate of the samples left this epoch. Use `len_hint` to know whether it's exact
     pub fn len(&mut self) -> usize {
-        let pipeline_data = if let Some(p) = &self.pipeline {
-            let l = p.data_remaining(0);
-            self.last_pipeline_length = l;
-            l
-        } else {
-            self.last_pipeline_length
-        };
-        pipeline_data + self.buffer.len()
+        let (length, _) = self.pipeline_size();
+        let (pending, skipped) = self.buffered();
+        length.saturating_add(pending).saturating_sub(skipped)
+    }
+
+    /// Bounds on the samples left this epoch
+    pub fn len_hint(&mut self) -> SizeHint {
+        let (_, hint) = self.pipeline_size();
+        let (pending, skipped) = self.buffered();
+        let remaining = |n: usize| n.saturating_add(pending).saturating_sub(skipped);
+        SizeHint {
+            lower: remaining(hint.lower),
+            upper: hint.upper.map(remaining),
+        }
+    }
+
+    fn pipeline_size(&mut self) -> (usize, SizeHint) {
+        // Don't wait on a worker that is currently processing
+        if let Ok(pipeline) = self.shared.pipeline.try_lock() {
+            self.last_pipeline_size = pipeline.size(self.shared.in_flight.load(Ordering::SeqCst));
+        }
+        self.last_pipeline_size
+    }
+
+    /// Samples loaded but not returned yet, and samples the pipeline will load that were delivered before a restore
+    fn buffered(&self) -> (usize, usize) {
+        let skipped = self.shared.skip.lock().unwrap().values().sum();
+        (self.shared.pending.load(Ordering::SeqCst), skipped)
     }
 
     pub fn is_empty(&mut self) -> bool {
         self.len() == 0
     }
 
-    pub fn iter_len(&mut self) -> LenIterDataloader<T> {
+    pub fn iter_len(&mut self) -> LenIterDataloader<'_, T> {
         LenIterDataloader { dataloader: self }
     }
 }
 
+impl<T: Send + 'static, E: Send + 'static> Dataloader<Result<T, E>> {
+    /// Create a dataloader from a fallible pipeline. A block that fails to load comes out as a single error, and loading continues after it.
+    pub fn new_fallible(
+        pipeline: impl TryNode<Vec<()>, Output = Vec<T>, Error = E> + Send + 'static,
+    ) -> Self {
+        Dataloader::new(Fallible::new(pipeline))
+    }
+}
+
+/// Load blocks until the source runs out of data. Returns false if the dataloader hung up
+fn load_epoch<T>(
+    shared: &Shared<T>,
+    sender: &SyncSender<Message<T>>,
+    epoch: Epoch,
+    load_block_size: usize,
+    worker: usize,
+) -> bool {
+    loop {
+        let (taken, load, block, skip) = {
+            let mut pipeline = shared.pipeline.lock().unwrap();
+            if pipeline.is_empty() {
+                return true;
+            }
+            let state = pipeline.state_dict();
+            let (taken, load) = pipeline.take(load_block_size, worker);
+            shared.in_flight.fetch_add(taken, Ordering::SeqCst);
+            let block = shared.blocks_loaded.fetch_add(1, Ordering::SeqCst);
+            let skip = shared
+                .skip
+                .lock()
+                .unwrap()
+                .get(&block)
+                .copied()
+                .unwrap_or(0);
+            // Announce the block while holding the source so blocks arrive in order
+            let message = Message::Block {
+                index: block,
+                state,
+                skipped: skip,
+            };
+            if !send(shared, sender, message) {
+                return false;
+            }
+            (taken, load, block, skip)
+        };
+        let mut data = load(epoch.child(2).child(block as u64));
+        let skip = skip.min(data.len());
+        // Count the samples as pending before they stop counting as in flight, so the length never dips
+        shared
+            .pending
+            .fetch_add(data.len() - skip, Ordering::SeqCst);
+        shared.skip.lock().unwrap().remove(&block);
+        shared.in_flight.fetch_sub(taken, Ordering::SeqCst);
+        let message = Message::Loaded {
+            index: block,
+            size: data.len(),
+        };
+        if !send(shared, sender, message) {
+            return false;
+        }
+        data.shuffle(&mut epoch.child(1).child(block as u64).rng());
+        for sample in data.into_iter().skip(skip) {
+            // Count before sending so the dataloader never receives an uncounted sample
+            shared.queued.fetch_add(1, Ordering::SeqCst);
+            if !send(shared, sender, Message::Sample(sample, block)) {
+                return false;
+            }
+        }
+    }
+}
+
+/// Send a message to the dataloader and wake it if it's waiting asynchronously. Returns false if the dataloader hung up
+fn send<T>(shared: &Shared<T>, sender: &SyncSender<Message<T>>, message: Message<T>) -> bool {
+    if sender.send(message).is_err() {
+        return false;
+    }
+    if let Some(waker) = shared.waker.lock().unwrap().take() {
+        waker.wake();
+    }
+    true
+}
+
 impl<T: Send + 'static> Iterator for Dataloader<T> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.start_epoch();
         loop {
-            // Check if the loading thread is finished
-            if let Some(process) = &self.loading_process {
-                if process.is_finished() || self.buffer.is_empty() {
-                    // Unload thread
-                    let process = self.loading_process.take().unwrap();
-                    let (pipeline, data) = process.join().unwrap();
-                    self.pipeline = Some(pipeline);
-                    self.buffer.extend(data);
-                }
-            }
-            // Launch thread if not currently running and buffer running low
-            if self.buffer.len() < self.buffer_size && self.pipeline.is_some() {
-                if self.pipeline.as_ref().unwrap().data_remaining(0) == 0 && self.buffer.is_empty()
-                {
-                    self.pipeline.as_mut().unwrap().reset();
-                    return None;
-                }
-                self.load_block();
+            let waiting = Instant::now();
+            let message = self.receiver.as_ref().unwrap().recv();
+            self.blocked += waiting.elapsed();
+            if let Some(item) = self.receive(message.ok()?) {
+                return item;
             }
+        }
+    }
+}
 
-            // Get data from buffer
-            if let Some(d) = self.buffer.pop_front() {
-                return Some(d);
-            } else if let Some(process) = self.loading_process.take() {
-                let (pipeline, data) = process.join().unwrap();
-                self.pipeline = Some(pipeline);
-                self.buffer.extend(data);
-                if let Some(d) = self.buffer.pop_front() {
-                    return Some(d);
-                }
+/// Async access to the dataloader. Like the iterator, the stream ends after each epoch.
+#[cfg(feature = "async")]
+impl<T: Send + 'static> futures::Stream for Dataloader<T> {
+    type Item = T;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::{sync::mpsc::TryRecvError, task::Poll};
+
+        self.start_epoch();
+        // Register before checking the queue so a message sent in between still wakes us
+        *self.shared.waker.lock().unwrap() = Some(cx.waker().clone());
+        loop {
+            let message = match self.receiver.as_ref().unwrap().try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => return Poll::Pending,
+                Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            };
+            if let Some(item) = self.receive(message) {
+                return Poll::Ready(item);
             }
         }
     }
 }
 
+impl<T> Dataloader<T> {
+    fn stop_workers(&mut self) {
+        // Closing the queue stops workers mid-epoch, closing the start channels stops idle workers
+        self.receiver.take();
+        for worker in self.workers.drain(..) {
+            drop(worker.start);
+            worker.handle.join().ok();
+        }
+        self.running_workers = 0;
+    }
+}
+
+impl<T> Drop for Dataloader<T> {
+    fn drop(&mut self) {
+        self.stop_workers();
+    }
+}
+
 pub struct LenIterDataloader<'a, T> {
     dataloader: &'a mut Dataloader<T>,
 }
@@ -1,6 +1,8 @@
+use std::sync::{Arc, Mutex};
+
 use super::Dataloader;
 use crate::pipeline::*;
-use rand::{prelude::SliceRandom, thread_rng};
+use rand::prelude::SliceRandom;
 
 /// A "loader" to load a full range of numbers randomly
 struct CreateRange {
@@ -27,8 +29,9 @@ impl Node<Vec<()>> for CreateRange {
         data
     }
 
-    fn reset(&mut self) {
-        self.nums_to_make.shuffle(&mut thread_rng());
+    fn reset(&mut self, epoch: Epoch) {
+        self.nums_to_make.sort_unstable();
+        self.nums_to_make.shuffle(&mut epoch.rng());
         self.current_progress = 0;
     }
 
@@ -49,7 +52,7 @@ fn test_dataloader() {
     // Run for 5_000 steps and collect results
     let mut data = Vec::with_capacity(10_000);
     for example in &mut loader {
-        data.extend(example.into_iter());
+        data.extend(example);
         if data.len() == 5_000 {
             break;
         }
@@ -60,9 +63,9 @@ fn test_dataloader() {
 
     // Run for the rest of the data and store it
     for example in &mut loader {
-        data.extend(example.into_iter());
+        data.extend(example);
     }
-    assert_eq!(loader.len(), 1000); // Make sure the loader reset
+    assert_eq!(loader.len(), 0); // The finished epoch isn't reset until the next one starts
 
     // Sort read data
     data.sort_unstable();
@@ -70,3 +73,225 @@ fn test_dataloader() {
     // Compare data
     assert_eq!(data, (0..10_000).map(|i| i * 10).collect::<Vec<usize>>())
 }
+
+#[test]
+fn test_multi_worker_dataloader() {
+    let mut loader = Dataloader::new(CreateRange::new(10_000))
+        .workers(4)
+        .load_block_size(100)
+        .buffer_size(50);
+
+    // Every number should come out exactly once per epoch
+    for _ in 0..2 {
+        let mut data = (&mut loader).collect::<Vec<_>>();
+        data.sort_unstable();
+        assert_eq!(data, (0..10_000).collect::<Vec<usize>>());
+        assert_eq!(loader.len(), 0);
+    }
+
+    // Dropping mid-epoch should shut down the workers blocked on the full queue
+    assert_eq!(loader.by_ref().take(10).count(), 10);
+    drop(loader);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_dataloader_stream() {
+    let mut loader = Dataloader::new(CreateRange::new(1000))
+        .workers(2)
+        .load_block_size(100)
+        .buffer_size(10);
+
+    // The stream should end after each epoch, just like the iterator
+    for _ in 0..2 {
+        let mut data = futures::StreamExt::collect::<Vec<_>>(&mut loader).await;
+        data.sort_unstable();
+        assert_eq!(data, (0..1000).collect::<Vec<usize>>());
+    }
+}
+
+#[test]
+fn test_seeded_dataloader() {
+    let load_epochs = |seed: u64| {
+        let pipeline = CreateRange::new(1000)
+            .chain(Shuffle::default())
+            .chain(Batch::new(10));
+        let mut loader = Dataloader::new(pipeline).seed(seed).load_block_size(100);
+        (0..2)
+            .map(|_| (&mut loader).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+    };
+
+    // The same seed and epoch should always give the same order
+    let (first_run, second_run) = (load_epochs(42), load_epochs(42));
+    assert_eq!(first_run, second_run);
+    // Different epochs and seeds should give different orders
+    assert_ne!(first_run[0], first_run[1]);
+    assert_ne!(first_run, load_epochs(43));
+}
+
+#[test]
+fn test_fallible_dataloader() {
+    let dir = std::env::temp_dir().join("dataflow_fallible_dataloader_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut files = (0..4)
+        .map(|i| {
+            let path = dir.join(format!("{i}.txt"));
+            std::fs::write(&path, i.to_string()).unwrap();
+            path
+        })
+        .collect::<Vec<_>>();
+    files.push(dir.join("missing.txt"));
+
+    // The missing file should come out as an error without stopping the loader
+    let loader = Dataloader::new_fallible(FileLoader::new(files));
+    let (loaded, errors): (Vec<_>, Vec<_>) = loader.partition(|r| r.is_ok());
+    assert_eq!(loaded.len(), 4);
+    assert_eq!(errors.len(), 1);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_checkpoint_dataloader() {
+    let make_loader = |workers: usize| {
+        let pipeline = VecLoader::new((0..1000).collect::<Vec<usize>>())
+            .shuffle(true)
+            .chain(Shuffle::default());
+        Dataloader::new(pipeline)
+            .seed(42)
+            .load_block_size(100)
+            .buffer_size(10)
+            .workers(workers)
+    };
+
+    // A restored loader should give exactly the samples that weren't delivered, in the same order
+    let mut loader = make_loader(1);
+    let delivered = (&mut loader).take(250).collect::<Vec<_>>();
+    let checkpoint: super::Checkpoint =
+        serde_json::from_str(&serde_json::to_string(&loader.checkpoint()).unwrap()).unwrap();
+    let remaining = (&mut loader).collect::<Vec<_>>();
+    let mut restored = make_loader(1);
+    restored.restore(&checkpoint);
+    assert_eq!(restored.len(), 750);
+    assert_eq!(restored.collect::<Vec<_>>(), remaining);
+    assert_eq!(delivered.len() + remaining.len(), 1000);
+
+    // With multiple workers the order may change, but no sample is lost or repeated
+    let mut loader = make_loader(4);
+    let mut delivered = (&mut loader).take(333).collect::<Vec<_>>();
+    let checkpoint = loader.checkpoint();
+    let mut restored = make_loader(4);
+    restored.restore(&checkpoint);
+    delivered.extend(restored);
+    delivered.sort_unstable();
+    assert_eq!(delivered, (0..1000).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_dataloader_epochs() {
+    let numbers = || VecLoader::new((0..100).collect::<Vec<usize>>());
+    let ended = Arc::new(Mutex::new(vec![]));
+    let hook_ended = ended.clone();
+    let mut loader = Dataloader::new(numbers())
+        .seed(0)
+        .epochs(3)
+        .load_block_size(10)
+        .on_epoch_end(move |epoch| hook_ended.lock().unwrap().push(epoch.index));
+
+    // One pass runs three epochs back to back, calling the hook after each
+    assert_eq!((&mut loader).count(), 300);
+    assert_eq!(*ended.lock().unwrap(), vec![0, 1, 2]);
+
+    // The last epoch stays finished until more samples are requested
+    assert_eq!(loader.epoch().index, 2);
+    assert_eq!(loader.len(), 0);
+    let checkpoint = loader.checkpoint();
+    assert!(loader.next().is_some());
+    assert_eq!(loader.epoch().index, 3);
+
+    // A checkpoint taken between epochs resumes at the start of the next one
+    let mut restored = Dataloader::new(numbers()).seed(0);
+    restored.restore(&checkpoint);
+    assert_eq!(restored.epoch().index, 3);
+    assert_eq!(restored.count(), 100);
+}
+
+#[test]
+fn test_dataloader_len_hint() {
+    let pipeline =
+        VecLoader::new((0..1000).collect::<Vec<usize>>()).filter(|i: &usize| i.is_multiple_of(2));
+    let mut loader = Dataloader::new(pipeline).load_block_size(100);
+
+    // The filter may drop anything, so the length is only an upper bound
+    let hint = loader.len_hint();
+    assert_eq!((hint.lower, hint.upper), (0, Some(1000)));
+    assert!(!hint.is_exact());
+    assert_eq!(loader.len(), 1000);
+    assert_eq!((&mut loader).count(), 500);
+
+    // An unfiltered pipeline is exact
+    let mut loader = Dataloader::new(VecLoader::new((0..1000).collect::<Vec<usize>>()));
+    assert_eq!(loader.len_hint(), SizeHint::exact(1000));
+    let _ = (&mut loader).take(10).count();
+    assert!(loader.len_hint().is_exact());
+}
+
+#[test]
+fn test_cycle_dataloader() {
+    let pipeline = VecLoader::new((0..10).collect::<Vec<usize>>()).cycle();
+    let mut loader = Dataloader::new(pipeline).load_block_size(4);
+
+    // A cycled pipeline never runs out, so the length saturates instead of overflowing
+    assert_eq!(loader.len(), usize::MAX);
+    assert_eq!(loader.len_hint().upper, None);
+    let samples = (&mut loader).take(25).collect::<Vec<_>>();
+    assert_eq!(samples.len(), 25);
+    assert!((0..10).all(|i| samples.contains(&i)));
+    assert_eq!(loader.len(), usize::MAX);
+}
+
+#[test]
+fn test_dataloader_metrics() {
+    let pipeline = VecLoader::new((0..1000).collect::<Vec<usize>>())
+        .profile("load")
+        .chain(Batch::new(10).profile("batch"));
+    let mut loader = Dataloader::new(pipeline).load_block_size(100);
+    assert_eq!(loader.metrics().samples, 0);
+
+    assert_eq!((&mut loader).count(), 100);
+    let metrics = loader.metrics();
+    assert_eq!(metrics.samples, 100);
+    assert!(metrics.samples_per_sec > 0.);
+    assert_eq!(metrics.buffered, 0);
+    assert_eq!(metrics.stages.len(), 2);
+    assert_eq!(metrics.stages[0].items_out, 1000);
+    assert_eq!(metrics.stages[1].items_out, 100);
+}
+
+#[test]
+fn test_parallel_dataloader() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Stages that note how many workers are inside them at once
+    let (active, most_active) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let (stage_active, stage_most_active) = (active.clone(), most_active.clone());
+    let stages = move |block: Vec<usize>| {
+        let now = stage_active.fetch_add(1, Ordering::SeqCst) + 1;
+        stage_most_active.fetch_max(now, Ordering::SeqCst);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stage_active.fetch_sub(1, Ordering::SeqCst);
+        block.into_iter().map(|i| i * 10).collect::<Vec<_>>()
+    };
+    let mut loader = Dataloader::parallel(CreateRange::new(400), stages)
+        .workers(4)
+        .load_block_size(10);
+    assert_eq!(loader.len(), 400);
+
+    // Blocks should go through the stages on several workers at once, and every sample should come out once
+    let mut data = (&mut loader).collect::<Vec<_>>();
+    data.sort_unstable();
+    assert_eq!(data, (0..400).map(|i| i * 10).collect::<Vec<usize>>());
+    assert!(most_active.load(Ordering::SeqCst) > 1);
+    assert_eq!(loader.len(), 0);
+}
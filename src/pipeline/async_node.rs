@@ -0,0 +1,80 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use super::{Epoch, Node, SizeHint};
+
+/// A node that processes data asynchronously, such as a remote source
+pub trait AsyncNode<Input> {
+    type Output;
+
+    /// Process a batch of data
+    fn async_process(&mut self, input: Input) -> impl Future<Output = Self::Output> + Send;
+    /// Reset signal propogates through pipeline
+    fn reset(&mut self, _epoch: Epoch) {}
+    /// Get number of examples left
+    fn data_remaining(&self, before: usize) -> usize {
+        before
+    } // Defaults to same as previous remaining data
+    /// Bounds on the number of examples left, given the bounds of the node before. Defaults to mapping both bounds through data_remaining
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.map(|b| self.data_remaining(b))
+    }
+}
+
+impl<I, O, Fut: Future<Output = O> + Send, F: FnMut(I) -> Fut> AsyncNode<I> for F {
+    type Output = O;
+    fn async_process(&mut self, input: I) -> impl Future<Output = Self::Output> + Send {
+        (self)(input)
+    }
+}
+
+/// Runs a sync node on tokio's blocking thread pool, so it can be awaited without stalling the runtime
+pub struct Blocking<N> {
+    node: Arc<Mutex<N>>,
+}
+
+impl<N> Clone for Blocking<N> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+        }
+    }
+}
+
+impl<N> Blocking<N> {
+    pub fn new(node: N) -> Self {
+        Blocking {
+            node: Arc::new(Mutex::new(node)),
+        }
+    }
+}
+
+impl<I: Send + 'static, N: Node<I> + Send + 'static> AsyncNode<I> for Blocking<N>
+where
+    N::Output: Send + 'static,
+{
+    type Output = N::Output;
+
+    fn async_process(&mut self, input: I) -> impl Future<Output = Self::Output> + Send {
+        let node = self.node.clone();
+        async move {
+            tokio::task::spawn_blocking(move || node.lock().unwrap().process(input))
+                .await
+                .unwrap()
+        }
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.lock().unwrap().reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.lock().unwrap().data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.lock().unwrap().size_hint(before)
+    }
+}
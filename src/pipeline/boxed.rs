@@ -0,0 +1,151 @@
+use super::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+
+/// A type-erased node, so pipelines can be named, stored in fields and chosen at runtime
+pub struct BoxedNode<I, O> {
+    node: Box<dyn Node<I, Output = O> + Send>,
+}
+
+impl<I, O> BoxedNode<I, O> {
+    pub fn new(node: impl Node<I, Output = O> + Send + 'static) -> Self {
+        BoxedNode {
+            node: Box::new(node),
+        }
+    }
+}
+
+impl<I, O> Node<I> for BoxedNode<I, O> {
+    type Output = O;
+
+    fn process(&mut self, input: I) -> Self::Output {
+        self.node.process(input)
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        self.node.describe()
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeBoxed<Input, Output, E: Node<Input, Output = Output>> {
+    /// Erase the node's type
+    fn boxed(self) -> BoxedNode<Input, Output>;
+}
+
+impl<Input, Output, E: Node<Input, Output = Output> + Send + 'static>
+    ExtendNodeBoxed<Input, Output, E> for E
+{
+    fn boxed(self) -> BoxedNode<Input, Output> {
+        BoxedNode::new(self)
+    }
+}
+
+/// Builds a pipeline one boxed stage at a time, so the stages can be picked at runtime
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let lowercase = true;
+/// let pipeline = DynPipeline::new(VecLoader::new(vec!["Hello".to_string(), "World".to_string()]))
+///     .add_stage_if(lowercase, |s: Vec<String>| s.into_iter().map(|s| s.to_lowercase()).collect())
+///     .add_stage(|s: Vec<String>| s.into_iter().map(|s| s.len()).collect::<Vec<_>>());
+/// let lengths: BoxedNode<Vec<()>, Vec<usize>> = pipeline.build();
+/// ```
+pub struct DynPipeline<I, O> {
+    node: BoxedNode<I, O>,
+}
+
+impl<I: 'static, O: 'static> DynPipeline<I, O> {
+    /// Start a pipeline from its first stage
+    pub fn new(node: impl Node<I, Output = O> + Send + 'static) -> Self {
+        DynPipeline {
+            node: BoxedNode::new(node),
+        }
+    }
+
+    /// Append a stage
+    pub fn add_stage<O2: 'static>(
+        self,
+        node: impl Node<O, Output = O2> + Send + 'static,
+    ) -> DynPipeline<I, O2> {
+        DynPipeline {
+            node: BoxedNode::new((self.node, node)),
+        }
+    }
+
+    /// Append a stage that keeps the output type, only if the condition holds
+    pub fn add_stage_if(
+        self,
+        condition: bool,
+        node: impl Node<O, Output = O> + Send + 'static,
+    ) -> Self {
+        if condition {
+            self.add_stage(node)
+        } else {
+            self
+        }
+    }
+
+    pub fn build(self) -> BoxedNode<I, O> {
+        self.node
+    }
+}
+
+impl<I, O> Node<I> for DynPipeline<I, O> {
+    type Output = O;
+
+    fn process(&mut self, input: I) -> Self::Output {
+        self.node.process(input)
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        self.node.describe()
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    convert::Infallible,
+    fmt::{self, Display},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::*;
+
+type AnyValue = Box<dyn Any + Send>;
+
+/// Register nodes once per type. The type is bound to the given name inside the body
+macro_rules! register_each {
+    ($registry:ident, [$($t:ty),+], $name:ident => $body:block) => {
+        $(
+            $registry = {
+                type $name = $t;
+                $body
+            };
+        )+
+    };
+}
+
+/// One stage of a pipeline config: the name a node is registered under and its parameters
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageConfig {
+    pub node: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// A pipeline as a list of stages, usually deserialized from a TOML or JSON config
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    pub stages: Vec<StageConfig>,
+}
+
+/// Why a pipeline couldn't be built from a config. Stages are indexed from 0
+#[derive(Debug)]
+pub enum ConfigError {
+    /// No node is registered under this name
+    UnknownNode { stage: usize, node: String },
+    /// The node is registered, but doesn't take what the previous stage outputs
+    TypeMismatch {
+        stage: usize,
+        node: String,
+        input: &'static str,
+        accepted: Vec<&'static str>,
+    },
+    /// The parameters don't match the node's parameters
+    Params {
+        stage: usize,
+        node: String,
+        error: serde_json::Error,
+    },
+    /// The last stage doesn't output the type the pipeline was built for
+    OutputMismatch {
+        output: &'static str,
+        expected: &'static str,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownNode { stage, node } => {
+                write!(f, "Stage {stage}: no node is registered as {node}")
+            }
+            ConfigError::TypeMismatch {
+                stage,
+                node,
+                input,
+                accepted,
+            } => write!(
+                f,
+                "Stage {stage}: {node} can't take {input} from the previous stage, it takes {}",
+                accepted.join(" or ")
+            ),
+            ConfigError::Params { stage, node, error } => {
+                write!(f, "Stage {stage}: bad parameters for {node}: {error}")
+            }
+            ConfigError::OutputMismatch { output, expected } => {
+                write!(f, "The pipeline outputs {output}, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Parameters for nodes that don't take any
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoParams {}
+
+/// A node building function registered for one input type
+struct Registration {
+    input: TypeId,
+    input_name: &'static str,
+    output: TypeId,
+    output_name: &'static str,
+    #[allow(clippy::type_complexity)]
+    build: Box<
+        dyn Fn(Value) -> Result<BoxedNode<AnyValue, AnyValue>, serde_json::Error> + Send + Sync,
+    >,
+}
+
+/// Nodes registered under names, so pipelines can be built from config documents.
+/// A name can be registered for several input types, the stage before it decides which one is used.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let config: PipelineConfig = serde_json::from_str(r#"{"stages": [
+///     {"node": "VecLoader", "params": {"elements": ["a", "b", "c"]}},
+///     {"node": "Batch", "params": {"batch_size": 2}}
+/// ]}"#).unwrap();
+/// let pipeline = NodeRegistry::premade()
+///     .build::<Vec<()>, Vec<Vec<String>>>(&config)
+///     .unwrap();
+/// assert_eq!(pipeline.run(10), vec![vec!["a".to_string(), "b".to_string()], vec!["c".to_string()]]);
+/// ```
+#[derive(Default)]
+pub struct NodeRegistry {
+    nodes: HashMap<String, Vec<Registration>>,
+}
+
+impl NodeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with the premade nodes and loaders, registered for common sample types
+    pub fn premade() -> Self {
+        let mut registry = Self::new()
+            .try_register::<Vec<()>, _, _, _>("FileLoader", |p: FileLoaderParams| match p {
+                FileLoaderParams::Files { files } => {
+                    check_files_exist(&files)?;
+                    Ok(FileLoader::new(files))
+                }
+                FileLoaderParams::Directory { directory } => {
+                    FileLoader::try_from_directory(&directory)
+                        .map_err(|e| format!("can't read {}: {e}", directory.display()))
+                }
+            })
+            .try_register::<Vec<usize>, _, _, _>("KeyedLoader", |p: KeyedLoaderParams| {
+                check_files_exist(&p.files)?;
+                let files = p.files.iter().map(|f| f.as_str()).collect::<Vec<_>>();
+                KeyedLoader::try_new(&files, &p.delimiter).map_err(|e| e.to_string())
+            });
+        // Loaders try each element type in order, so strings and unsigned numbers come first
+        register_each!(registry, [
+            String, usize, i64, f64, bool, Vec<String>, Vec<usize>, (PathBuf, Vec<u8>)
+        ], T => {
+            registry
+                .register::<Vec<()>, _, _>("VecLoader", |p: VecLoaderParams<T>| {
+                    VecLoader::new(p.elements).shuffle(p.shuffle)
+                })
+                .register::<Vec<T>, _, _>("Batch", |p: BatchParams| Batch::<T>::new(p.batch_size))
+                .register::<Vec<T>, _, _>("Shuffle", |_: NoParams| Shuffle::<T>::default())
+                .register::<Vec<Vec<T>>, _, _>("Flatten", |_: NoParams| Flatten)
+        });
+        register_each!(registry, [
+            String, usize, i64, bool, Vec<String>, Vec<usize>, (PathBuf, Vec<u8>)
+        ], T => {
+            registry.register::<Vec<T>, _, _>("Sort", |p: SortParams| {
+                Sort::new(move |a: &T, b: &T| if p.descending { b.cmp(a) } else { a.cmp(b) })
+            })
+        });
+        register_each!(registry, [String, Vec<String>, Vec<usize>], T => {
+            registry.register::<Vec<T>, _, _>("Filter", |p: LengthParams| {
+                Filter::new(move |s: &T| {
+                    p.min_len.is_none_or(|min| s.length() >= min)
+                        && p.max_len.is_none_or(|max| s.length() <= max)
+                })
+            })
+        });
+        registry
+    }
+
+    /// Register a node for an input type, built from deserialized parameters
+    pub fn register<I, P, N>(
+        self,
+        name: &str,
+        build: impl Fn(P) -> N + Send + Sync + 'static,
+    ) -> Self
+    where
+        I: Send + 'static,
+        P: DeserializeOwned,
+        N: Node<I> + Send + 'static,
+        N::Output: Send + 'static,
+    {
+        self.try_register::<I, _, _, Infallible>(name, move |p| Ok(build(p)))
+    }
+
+    /// Register a node that can fail to build, like a loader whose files don't exist.
+    /// Failures are reported as bad parameters for the stage
+    pub fn try_register<I, P, N, E>(
+        mut self,
+        name: &str,
+        build: impl Fn(P) -> Result<N, E> + Send + Sync + 'static,
+    ) -> Self
+    where
+        I: Send + 'static,
+        P: DeserializeOwned,
+        N: Node<I> + Send + 'static,
+        N::Output: Send + 'static,
+        E: Display,
+    {
+        self.nodes
+            .entry(name.to_string())
+            .or_default()
+            .push(Registration {
+                input: TypeId::of::<I>(),
+                input_name: type_name::<I>(),
+                output: TypeId::of::<N::Output>(),
+                output_name: type_name::<N::Output>(),
+                build: Box::new(move |params| {
+                    let node =
+                        build(serde_json::from_value(params)?).map_err(serde::de::Error::custom)?;
+                    Ok(BoxedNode::new(Erased {
+                        node,
+                        _phantom: PhantomData::<fn(I)>,
+                    }))
+                }),
+            });
+        self
+    }
+
+    /// Build a pipeline from a config, checking that each stage takes what the one before it outputs
+    pub fn build<I: Send + 'static, O: 'static>(
+        &self,
+        config: &PipelineConfig,
+    ) -> Result<ConfigPipeline<I, O>, ConfigError> {
+        let (mut current, mut current_name) = (TypeId::of::<I>(), type_name::<I>());
+        let mut stages = Vec::with_capacity(config.stages.len());
+        for (stage, StageConfig { node, params }) in config.stages.iter().enumerate() {
+            let registrations = self
+                .nodes
+                .get(node)
+                .ok_or_else(|| ConfigError::UnknownNode {
+                    stage,
+                    node: node.clone(),
+                })?;
+            let candidates = registrations.iter().filter(|r| r.input == current);
+            // Missing parameters are the same as no parameters
+            let params = match params {
+                Value::Null => Value::Object(Default::default()),
+                params => params.clone(),
+            };
+            let mut error = None;
+            let built = candidates
+                .filter_map(|r| match (r.build)(params.clone()) {
+                    Ok(built) => Some((r, built)),
+                    Err(e) => {
+                        error.get_or_insert(e);
+                        None
+                    }
+                })
+                .next();
+            let Some((registration, built)) = built else {
+                return Err(match error {
+                    Some(error) => ConfigError::Params {
+                        stage,
+                        node: node.clone(),
+                        error,
+                    },
+                    None => ConfigError::TypeMismatch {
+                        stage,
+                        node: node.clone(),
+                        input: current_name,
+                        accepted: registrations.iter().map(|r| r.input_name).collect(),
+                    },
+                });
+            };
+            (current, current_name) = (registration.output, registration.output_name);
+            stages.push(built);
+        }
+        if current != TypeId::of::<O>() {
+            return Err(ConfigError::OutputMismatch {
+                output: current_name,
+                expected: type_name::<O>(),
+            });
+        }
+        Ok(ConfigPipeline {
+            stages,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// A pipeline built from a config
+pub struct ConfigPipeline<I, O> {
+    stages: Vec<BoxedNode<AnyValue, AnyValue>>,
+    _phantom: PhantomData<fn(I) -> O>,
+}
+
+impl<I: Send + 'static, O: 'static> Node<I> for ConfigPipeline<I, O> {
+    type Output = O;
+
+    fn process(&mut self, input: I) -> Self::Output {
+        let mut x: AnyValue = Box::new(input);
+        for stage in &mut self.stages {
+            x = stage.process(x);
+        }
+        // Types were checked when the pipeline was built
+        *x.downcast::<O>().unwrap()
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        for (i, stage) in self.stages.iter_mut().enumerate() {
+            stage.reset(epoch.child(i as u64));
+        }
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.stages
+            .iter()
+            .fold(before, |before, stage| stage.data_remaining(before))
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.stages
+            .iter()
+            .fold(before, |before, stage| stage.size_hint(before))
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(self.stages.iter().map(|s| s.state_dict()).collect())
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        for (stage, state) in self.stages.iter_mut().zip(state.list()) {
+            stage.load_state_dict(state);
+        }
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::chain(self.stages.iter().map(|s| s.describe()))
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.stages.iter().flat_map(|s| s.profiles()).collect()
+    }
+}
+
+/// A node taking and returning boxed values
+struct Erased<I, N> {
+    node: N,
+    _phantom: PhantomData<fn(I)>,
+}
+
+impl<I: 'static, N: Node<I>> Node<AnyValue> for Erased<I, N>
+where
+    N::Output: Send + 'static,
+{
+    type Output = AnyValue;
+
+    fn process(&mut self, input: AnyValue) -> Self::Output {
+        Box::new(self.node.process(*input.downcast::<I>().unwrap()))
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        self.node.describe()
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+/// Fail with the first file that doesn't exist, so loaders don't panic once the pipeline runs
+pub fn check_files_exist<P: AsRef<Path>>(files: &[P]) -> Result<(), String> {
+    match files.iter().find(|f| !f.as_ref().is_file()) {
+        Some(missing) => Err(format!("{} doesn't exist", missing.as_ref().display())),
+        None => Ok(()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, untagged)]
+enum FileLoaderParams {
+    Files { files: Vec<PathBuf> },
+    Directory { directory: PathBuf },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyedLoaderParams {
+    files: Vec<String>,
+    delimiter: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VecLoaderParams<T> {
+    elements: Vec<T>,
+    #[serde(default)]
+    shuffle: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchParams {
+    batch_size: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SortParams {
+    #[serde(default)]
+    descending: bool,
+}
+
+/// Keep samples with a length in a range
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+struct LengthParams {
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+}
+
+trait Length {
+    fn length(&self) -> usize;
+}
+
+impl Length for String {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
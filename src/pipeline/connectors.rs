@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use super::Node;
+use super::{Epoch, Flow, Node, NodeDescription, NodeState, Profile, SizeHint, TryNode};
 
 /// A node that takes in T and outputs (T, T)
 pub struct Duplicator<T: Clone> {
@@ -10,7 +10,7 @@ pub struct Duplicator<T: Clone> {
 impl<T: Clone> Default for Duplicator<T> {
     fn default() -> Self {
         Duplicator {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
         }
     }
 }
@@ -22,7 +22,7 @@ impl<T: Clone> Node<T> for Duplicator<T> {
     }
 }
 
-/// Pair contains two nodes that run in parallel (TODO: actually make parallel)
+/// Pair contains two nodes that run side by side on the same thread. Use `concurrent()` to run them on separate threads
 pub struct Pair<I0, I1, N1: Node<I0>, N2: Node<I1>> {
     pub node1: N1,
     pub node2: N2,
@@ -37,6 +37,11 @@ impl<I1, O1, N1: Node<I1, Output = O1>, I2, O2, N2: Node<I2, Output = O2>> Pair<
             _phantom: Default::default(),
         }
     }
+
+    /// Run the two nodes concurrently on separate threads
+    pub fn concurrent(self) -> ConcurrentPair<I1, I2, N1, N2> {
+        ConcurrentPair::new(self.node1, self.node2)
+    }
 }
 
 impl<I1, I2, N1: Node<I1>, N2: Node<I2>> Node<(I1, I2)> for Pair<I1, I2, N1, N2> {
@@ -46,9 +51,9 @@ impl<I1, I2, N1: Node<I1>, N2: Node<I2>> Node<(I1, I2)> for Pair<I1, I2, N1, N2>
         (self.node1.process(a), self.node2.process(b))
     }
 
-    fn reset(&mut self) {
-        self.node1.reset();
-        self.node2.reset();
+    fn reset(&mut self, epoch: Epoch) {
+        self.node1.reset(epoch.child(0));
+        self.node2.reset(epoch.child(1));
     }
 
     fn data_remaining(&self, before: usize) -> usize {
@@ -57,6 +62,215 @@ impl<I1, I2, N1: Node<I1>, N2: Node<I2>> Node<(I1, I2)> for Pair<I1, I2, N1, N2>
             self.node2.data_remaining(before),
         )
     }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node1
+            .size_hint(before)
+            .min(self.node2.size_hint(before))
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(vec![self.node1.state_dict(), self.node2.state_dict()])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node1.load_state_dict(&state.list()[0]);
+        self.node2.load_state_dict(&state.list()[1]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Pair")
+            .flow(Flow::Parallel)
+            .child(self.node1.describe())
+            .child(self.node2.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        let mut profiles = self.node1.profiles();
+        profiles.extend(self.node2.profiles());
+        profiles
+    }
+}
+
+/// ConcurrentPair contains two nodes that run in parallel. node2 runs on rayon's global pool while node1 runs on the current thread
+pub struct ConcurrentPair<I0, I1, N1: Node<I0>, N2: Node<I1>> {
+    pub node1: N1,
+    pub node2: N2,
+    _phantom: PhantomData<(I0, I1)>,
+}
+
+impl<I1, O1, N1: Node<I1, Output = O1>, I2, O2, N2: Node<I2, Output = O2>>
+    ConcurrentPair<I1, I2, N1, N2>
+{
+    pub fn new(node1: N1, node2: N2) -> Self {
+        ConcurrentPair {
+            node1,
+            node2,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<I1, I2: Send, N1: Node<I1>, N2: Node<I2> + Send> Node<(I1, I2)>
+    for ConcurrentPair<I1, I2, N1, N2>
+where
+    N2::Output: Send,
+{
+    type Output = (N1::Output, N2::Output);
+
+    fn process(&mut self, (a, b): (I1, I2)) -> Self::Output {
+        let (node1, node2) = (&mut self.node1, &mut self.node2);
+        let mut output2 = None;
+        let output1 = rayon::in_place_scope(|s| {
+            s.spawn(|_| output2 = Some(node2.process(b)));
+            node1.process(a)
+        });
+        (output1, output2.unwrap())
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node1.reset(epoch.child(0));
+        self.node2.reset(epoch.child(1));
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        usize::min(
+            self.node1.data_remaining(before),
+            self.node2.data_remaining(before),
+        )
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node1
+            .size_hint(before)
+            .min(self.node2.size_hint(before))
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(vec![self.node1.state_dict(), self.node2.state_dict()])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node1.load_state_dict(&state.list()[0]);
+        self.node2.load_state_dict(&state.list()[1]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("ConcurrentPair")
+            .flow(Flow::Parallel)
+            .child(self.node1.describe())
+            .child(self.node2.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        let mut profiles = self.node1.profiles();
+        profiles.extend(self.node2.profiles());
+        profiles
+    }
+}
+
+/// Lifts an infallible node into a TryNode that never fails
+pub struct Lift<N, E> {
+    pub node: N,
+    _phantom: PhantomData<E>,
+}
+
+impl<N, E> Lift<N, E> {
+    pub fn new(node: N) -> Self {
+        Lift {
+            node,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<I, N: Node<I>, E> TryNode<I> for Lift<N, E> {
+    type Output = N::Output;
+    type Error = E;
+
+    fn try_process(&mut self, input: I) -> Result<Self::Output, Self::Error> {
+        Ok(self.node.process(input))
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        // Lifting is an implementation detail of `then`, so describe the inner node
+        self.node.describe()
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+/// Turns a fallible node outputting batches into a node outputting a batch of results.
+/// A failed batch comes out as a single error, so downstream nodes and the Dataloader keep running.
+pub struct Fallible<N> {
+    pub node: N,
+}
+
+impl<N> Fallible<N> {
+    pub fn new(node: N) -> Self {
+        Fallible { node }
+    }
+}
+
+impl<I, T, N: TryNode<I, Output = Vec<T>>> Node<I> for Fallible<N> {
+    type Output = Vec<Result<T, N::Error>>;
+
+    fn process(&mut self, input: I) -> Self::Output {
+        match self.node.try_process(input) {
+            Ok(output) => output.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        }
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Fallible").child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
 }
 
 macro_rules! tuple_impls {
@@ -74,14 +288,35 @@ macro_rules! tuple_impls {
                 x
             }
 
-            fn reset(&mut self) {
-                $(self.$idx.reset();)+
+            fn reset(&mut self, epoch: Epoch) {
+                $(self.$idx.reset(epoch.child($idx));)+
             }
 
             fn data_remaining(&self, mut before: usize) -> usize {
                 $( before = self.$idx.data_remaining(before); )+
                 before
             }
+
+            fn size_hint(&self, mut before: SizeHint) -> SizeHint {
+                $( before = self.$idx.size_hint(before); )+
+                before
+            }
+
+            fn state_dict(&self) -> NodeState {
+                NodeState::List(vec![$(self.$idx.state_dict(),)+])
+            }
+
+            fn load_state_dict(&mut self, state: &NodeState) {
+                $(self.$idx.load_state_dict(&state.list()[$idx]);)+
+            }
+
+            fn describe(&self) -> NodeDescription {
+                NodeDescription::chain([$(self.$idx.describe(),)+])
+            }
+
+            fn profiles(&self) -> Vec<Profile> {
+                [$(self.$idx.profiles(),)+].concat()
+            }
         }
     };
 }
@@ -91,3 +326,61 @@ tuple_impls!([M1, M2, M3] [0, 1, 2], M3, [M2, M1]);
 tuple_impls!([M1, M2, M3, M4] [0, 1, 2, 3], M4, [M3, M2, M1]);
 tuple_impls!([M1, M2, M3, M4, M5] [0, 1, 2, 3, 4], M5, [M4, M3, M2, M1]);
 tuple_impls!([M1, M2, M3, M4, M5, M6] [0, 1, 2, 3, 4, 5], M6, [M5, M4, M3, M2, M1]);
+
+macro_rules! try_tuple_impls {
+    ([$($name:ident),+] [$($idx:tt),+], $last:ident, [$($rev_tail:ident),+]) => {
+        impl<
+            Input,
+            $last:
+            $(TryNode::<$rev_tail ::Output>, $rev_tail: )+
+            TryNode<Input>
+        > TryNode<Input> for ($($name,)+)
+        where
+            $($last ::Error: From<$rev_tail ::Error>,)+
+        {
+            type Output = $last ::Output;
+            type Error = $last ::Error;
+
+            fn try_process(&mut self, x: Input) -> Result<Self::Output, Self::Error> {
+                $(let x = self.$idx.try_process(x)?;)+
+                Ok(x)
+            }
+
+            fn reset(&mut self, epoch: Epoch) {
+                $(self.$idx.reset(epoch.child($idx));)+
+            }
+
+            fn data_remaining(&self, mut before: usize) -> usize {
+                $( before = self.$idx.data_remaining(before); )+
+                before
+            }
+
+            fn size_hint(&self, mut before: SizeHint) -> SizeHint {
+                $( before = self.$idx.size_hint(before); )+
+                before
+            }
+
+            fn state_dict(&self) -> NodeState {
+                NodeState::List(vec![$(self.$idx.state_dict(),)+])
+            }
+
+            fn load_state_dict(&mut self, state: &NodeState) {
+                $(self.$idx.load_state_dict(&state.list()[$idx]);)+
+            }
+
+            fn describe(&self) -> NodeDescription {
+                NodeDescription::chain([$(self.$idx.describe(),)+])
+            }
+
+            fn profiles(&self) -> Vec<Profile> {
+                [$(self.$idx.profiles(),)+].concat()
+            }
+        }
+    };
+}
+
+try_tuple_impls!([M1, M2] [0, 1], M2, [M1]);
+try_tuple_impls!([M1, M2, M3] [0, 1, 2], M3, [M2, M1]);
+try_tuple_impls!([M1, M2, M3, M4] [0, 1, 2, 3], M4, [M3, M2, M1]);
+try_tuple_impls!([M1, M2, M3, M4, M5] [0, 1, 2, 3, 4], M5, [M4, M3, M2, M1]);
+try_tuple_impls!([M1, M2, M3, M4, M5, M6] [0, 1, 2, 3, 4, 5], M6, [M5, M4, M3, M2, M1]);
@@ -0,0 +1,160 @@
+use std::fmt::{self, Display, Write};
+
+/// How the children of a node are connected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Flow {
+    /// Each child feeds the next one
+    #[default]
+    Sequence,
+    /// Children run side by side
+    Parallel,
+}
+
+/// A tree of pipeline stages and their parameters, returned by `Node::describe`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeDescription {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub flow: Flow,
+    pub children: Vec<NodeDescription>,
+}
+
+impl NodeDescription {
+    pub fn new(name: impl Into<String>) -> Self {
+        NodeDescription {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Describe a node by its type name, without module path or generics
+    pub fn of<T: ?Sized>() -> Self {
+        let name = std::any::type_name::<T>();
+        let name = name.split('<').next().unwrap_or(name);
+        match name.rsplit("::").next().unwrap_or(name) {
+            "{{closure}}" => Self::new("closure"),
+            name => Self::new(name),
+        }
+    }
+
+    /// Nodes chained one after another. Nested chains are flattened
+    pub fn chain(children: impl IntoIterator<Item = NodeDescription>) -> Self {
+        let mut chain = Self::new("Chain");
+        for child in children {
+            if child.name == "Chain" && child.params.is_empty() {
+                chain.children.extend(child.children);
+            } else {
+                chain.children.push(child);
+            }
+        }
+        chain
+    }
+
+    pub fn param(mut self, key: impl Into<String>, value: impl Display) -> Self {
+        self.params.push((key.into(), value.to_string()));
+        self
+    }
+
+    pub fn child(mut self, child: NodeDescription) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn flow(mut self, flow: Flow) -> Self {
+        self.flow = flow;
+        self
+    }
+
+    /// The name followed by the parameters, like `Batch(batch_size=32)`
+    pub fn label(&self) -> String {
+        if self.params.is_empty() {
+            return self.name.clone();
+        }
+        let params = self
+            .params
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({params})", self.name)
+    }
+
+    /// Render as an indented tree, one node per line
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        self.write_text(&mut text, 0);
+        text
+    }
+
+    fn write_text(&self, text: &mut String, depth: usize) {
+        let marker = match (self.children.len() > 1, self.flow) {
+            (true, Flow::Parallel) => " [parallel]",
+            _ => "",
+        };
+        writeln!(text, "{}{}{marker}", "  ".repeat(depth), self.label()).unwrap();
+        for child in &self.children {
+            child.write_text(text, depth + 1);
+        }
+    }
+
+    /// Render as a Graphviz DOT digraph. Leaves are stages, nodes with children become clusters
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph pipeline {\n    rankdir=LR;\n    node [shape=box];\n");
+        let mut next_id = 0;
+        self.write_dot(&mut dot, &mut next_id, 1);
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write this node and return the ids data enters and leaves through
+    fn write_dot(
+        &self,
+        dot: &mut String,
+        next_id: &mut usize,
+        depth: usize,
+    ) -> (Vec<usize>, Vec<usize>) {
+        let indent = "    ".repeat(depth);
+        let id = *next_id;
+        *next_id += 1;
+        let label = self.label().replace('\\', "\\\\").replace('"', "\\\"");
+        if self.children.is_empty() {
+            writeln!(dot, "{indent}n{id} [label=\"{label}\"];").unwrap();
+            return (vec![id], vec![id]);
+        }
+
+        writeln!(dot, "{indent}subgraph cluster_{id} {{").unwrap();
+        writeln!(dot, "{indent}    label=\"{label}\";").unwrap();
+        let ends = self
+            .children
+            .iter()
+            .map(|child| child.write_dot(dot, next_id, depth + 1))
+            .collect::<Vec<_>>();
+        writeln!(dot, "{indent}}}").unwrap();
+        match self.flow {
+            Flow::Sequence => {
+                for window in ends.windows(2) {
+                    for from in &window[0].1 {
+                        for to in &window[1].0 {
+                            writeln!(dot, "{indent}n{from} -> n{to};").unwrap();
+                        }
+                    }
+                }
+                (ends[0].0.clone(), ends[ends.len() - 1].1.clone())
+            }
+            Flow::Parallel => {
+                ends.into_iter()
+                    .fold((vec![], vec![]), |(mut entries, mut exits), (e, x)| {
+                        entries.extend(e);
+                        exits.extend(x);
+                        (entries, exits)
+                    })
+            }
+        }
+    }
+}
+
+impl Display for NodeDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_text())
+    }
+}
@@ -5,36 +5,39 @@ use std::{
 };
 
 use itertools::Itertools;
-use rand::{prelude::SliceRandom, thread_rng};
+use rand::prelude::SliceRandom;
 
 use crate::pipeline::*;
 
 #[derive(Clone)]
 pub struct FileLoader {
     files: Vec<PathBuf>,
+    load_order: Vec<usize>, // Indexes into files, reshuffled every epoch
     currently_loaded_index: usize, // The last example we loaded as an index of the load_order vector (starts at 0)
 }
 
 impl FileLoader {
-    pub fn new(mut files: Vec<PathBuf>) -> Self {
+    pub fn new(files: Vec<PathBuf>) -> Self {
         FileLoader {
-            files: {
-                files.shuffle(&mut thread_rng());
-                files
-            },
+            load_order: (0..files.len()).collect(),
+            files,
             currently_loaded_index: 0,
         }
     }
 
     pub fn from_directory<P: AsRef<Path>>(path: P) -> Self {
-        FileLoader {
-            files: std::fs::read_dir(path)
-                .unwrap()
-                .flatten()
-                .map(|f| f.path())
-                .collect_vec(),
-            currently_loaded_index: 0,
-        }
+        Self::try_from_directory(path).unwrap()
+    }
+
+    /// Create a new FileLoader with all files in a directory, failing if the directory can't be read
+    pub fn try_from_directory<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        // Sort the files, since directory order isn't stable
+        let files = std::fs::read_dir(path)?
+            .flatten()
+            .map(|f| f.path())
+            .sorted()
+            .collect_vec();
+        Ok(Self::new(files))
     }
 }
 
@@ -42,27 +45,75 @@ impl Node<Vec<()>> for FileLoader {
     type Output = Vec<(PathBuf, Vec<u8>)>;
 
     fn process(&mut self, input: Vec<()>) -> Self::Output {
+        self.try_process(input)
+            .expect("FileLoader failed to load file!")
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.load_order = (0..self.files.len()).collect();
+        self.load_order.shuffle(&mut epoch.rng());
+        self.currently_loaded_index = 0;
+    }
+
+    fn data_remaining(&self, _before: usize) -> usize {
+        self.files.len() - self.currently_loaded_index
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.currently_loaded_index)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.currently_loaded_index = state.index();
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("FileLoader").param("files", self.files.len())
+    }
+}
+
+/// Loads files until one fails. Files read before the failure are returned, and the error is returned on the next call.
+impl TryNode<Vec<()>> for FileLoader {
+    type Output = Vec<(PathBuf, Vec<u8>)>;
+    type Error = std::io::Error;
+
+    fn try_process(&mut self, input: Vec<()>) -> Result<Self::Output, Self::Error> {
         let mut read_data = vec![];
-        for file in self.files[self.currently_loaded_index
-            ..(self.currently_loaded_index + input.len()).min(self.files.len() - 1)]
-            .iter()
-        {
+        let end = (self.currently_loaded_index + input.len()).min(self.files.len());
+        while self.currently_loaded_index < end {
+            let file = &self.files[self.load_order[self.currently_loaded_index]];
             let mut data = Vec::new();
-            let mut f = File::open(file).expect("FileLoader failed to load file!");
-            f.read_to_end(&mut data).expect("Failed to read file!");
+            if let Err(e) = File::open(file).and_then(|mut f| f.read_to_end(&mut data)) {
+                if read_data.is_empty() {
+                    // Skip the failed file so the next call continues past it
+                    self.currently_loaded_index += 1;
+                    return Err(e);
+                }
+                return Ok(read_data);
+            }
             read_data.push((file.clone(), data));
+            self.currently_loaded_index += 1;
         }
-        self.currently_loaded_index =
-            (self.currently_loaded_index + input.len()).min(self.files.len());
-        read_data
+        Ok(read_data)
     }
 
-    fn reset(&mut self) {
-        self.files.shuffle(&mut thread_rng());
-        self.currently_loaded_index = 0;
+    fn reset(&mut self, epoch: Epoch) {
+        Node::reset(self, epoch);
     }
 
-    fn data_remaining(&self, _before: usize) -> usize {
-        self.files.len() - self.currently_loaded_index
+    fn data_remaining(&self, before: usize) -> usize {
+        Node::data_remaining(self, before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        Node::state_dict(self)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        Node::load_state_dict(self, state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        Node::describe(self)
     }
 }
@@ -17,37 +17,19 @@ pub struct KeyedLoader {
 
 impl KeyedLoader {
     pub fn new(files: &[&str], delimeter: &str) -> Self {
-        // Get file sizes
-        let file_sizes: Vec<usize> = files
-            .iter()
-            .map(|f| {
-                let file = File::open(f).unwrap();
-                let reader = BufReader::new(file);
-                let mut delimeter_count = 0;
-                if delimeter == "\n" {
-                    delimeter_count = reader.lines().count();
-                } else {
-                    for line in reader.lines().flatten() {
-                        delimeter_count += line.matches(delimeter).count();
-                    }
-                    delimeter_count += 1; // Since delimeters divide the examples, there should be 1 more example than delimeter
-                }
-                delimeter_count
-            })
-            .collect();
+        Self::try_new(files, delimeter).unwrap()
+    }
 
-        KeyedLoader {
+    /// Create a new KeyedLoader, failing if any of the files can't be read
+    pub fn try_new(files: &[&str], delimeter: &str) -> std::io::Result<Self> {
+        Ok(KeyedLoader {
+            file_sizes: count_examples(files, delimeter)?,
             files: files.iter().map(|s| s.to_string()).collect(),
-            file_sizes,
             delimeter: delimeter.to_string(),
-        }
+        })
     }
-}
 
-impl Node<Vec<usize>> for KeyedLoader {
-    type Output = Vec<String>;
-
-    fn process(&mut self, input: Vec<usize>) -> Self::Output {
+    fn load(&self, input: Vec<usize>) -> std::io::Result<Vec<String>> {
         // Get bounds to load from
         let (min, max) = input.iter().minmax().into_option().unwrap().to_owned();
         let (mut min, mut max) = (*min, *max);
@@ -66,12 +48,12 @@ impl Node<Vec<usize>> for KeyedLoader {
         }
         // Sort inputs and keep track of order (orig order, sorted indexes)
         let mut sorted_inputs: Vec<(usize, usize)> = input.into_iter().enumerate().collect();
-        sorted_inputs.sort_by(|a, b| a.1.cmp(&b.1));
+        sorted_inputs.sort_by_key(|a| a.1);
 
         // Load all segments from min to max
         let mut buffer = Vec::with_capacity(sorted_inputs.len());
         for file_index in min_file..max_file + 1 {
-            let file = File::open(&self.files[file_index]).unwrap();
+            let file = File::open(&self.files[file_index])?;
             let reader = BufReader::new(file);
 
             let mut index_counter = 0;
@@ -82,26 +64,26 @@ impl Node<Vec<usize>> for KeyedLoader {
                 self.file_sizes[file_index]
             };
             if self.delimeter == "\n" {
-                for line in reader.lines().flatten() {
+                for line in reader.lines().map_while(Result::ok) {
                     if segment_counter == sorted_inputs[index_counter].1 {
                         buffer.push(line);
                         index_counter += 1;
                         if index_counter == sorted_inputs.len() {
-                            return buffer;
+                            return Ok(buffer);
                         }
                     }
                     segment_counter += 1;
                 }
             } else {
                 let mut intermediate_segment = "".to_string();
-                for line in reader.lines().flatten() {
+                for line in reader.lines().map_while(Result::ok) {
                     let line_segments: Vec<&str> = line.split(&self.delimeter).collect();
 
                     if segment_counter == sorted_inputs[index_counter].1 {
                         buffer.push(format!("{}{}", intermediate_segment, line_segments[0]));
                         index_counter += 1;
                         if index_counter == sorted_inputs.len() {
-                            return buffer;
+                            return Ok(buffer);
                         }
                     }
                     for line_segment in line_segments
@@ -112,7 +94,7 @@ impl Node<Vec<usize>> for KeyedLoader {
                             buffer.push(line_segment.to_string());
                             index_counter += 1;
                             if index_counter == sorted_inputs.len() {
-                                return buffer;
+                                return Ok(buffer);
                             }
                         }
                     }
@@ -126,33 +108,83 @@ impl Node<Vec<usize>> for KeyedLoader {
             }
         }
 
-        buffer
+        Ok(buffer)
     }
+}
 
-    fn reset(&mut self) {
-        // Recalculate file sizes
-        let file_sizes = self
-            .files
-            .iter()
-            .map(|f| {
-                let file = File::open(f).unwrap();
-                let reader = BufReader::new(file);
-                let mut delimeter_count = 0;
-                if self.delimeter == "\n" {
-                    delimeter_count = reader.lines().count();
-                } else {
-                    for line in reader.lines().flatten() {
-                        delimeter_count += line.matches(&self.delimeter).count();
-                    }
-                    delimeter_count += 1; // Since delimeters divide the examples, there should be 1 more example than delimeter
+/// Count the examples in each file
+fn count_examples<S: AsRef<str>>(files: &[S], delimeter: &str) -> std::io::Result<Vec<usize>> {
+    files
+        .iter()
+        .map(|f| {
+            let file = File::open(f.as_ref())?;
+            let reader = BufReader::new(file);
+            let mut delimeter_count = 0;
+            if delimeter == "\n" {
+                delimeter_count = reader.lines().count();
+            } else {
+                for line in reader.lines().map_while(Result::ok) {
+                    delimeter_count += line.matches(delimeter).count();
                 }
-                delimeter_count
-            })
-            .collect();
-        self.file_sizes = file_sizes;
+                delimeter_count += 1; // Since delimeters divide the examples, there should be 1 more example than delimeter
+            }
+            Ok(delimeter_count)
+        })
+        .collect()
+}
+
+impl Node<Vec<usize>> for KeyedLoader {
+    type Output = Vec<String>;
+
+    fn process(&mut self, input: Vec<usize>) -> Self::Output {
+        self.load(input).unwrap()
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        // Recalculate file sizes
+        self.file_sizes = count_examples(&self.files, &self.delimeter).unwrap();
     }
 
     fn data_remaining(&self, before: usize) -> usize {
-        before
+        before.min(self.file_sizes.iter().sum())
+    }
+
+    /// Keys past the end of the files load nothing
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most().map(|b| Node::data_remaining(self, b))
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("KeyedLoader")
+            .param("files", self.files.len())
+            .param("delimiter", self.delimeter.escape_default())
+    }
+}
+
+impl TryNode<Vec<usize>> for KeyedLoader {
+    type Output = Vec<String>;
+    type Error = std::io::Error;
+
+    fn try_process(&mut self, input: Vec<usize>) -> Result<Self::Output, Self::Error> {
+        self.load(input)
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        // Keep the old sizes if recounting fails, the error will surface when loading
+        if let Ok(file_sizes) = count_examples(&self.files, &self.delimeter) {
+            self.file_sizes = file_sizes;
+        }
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        Node::data_remaining(self, before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        Node::size_hint(self, before)
+    }
+
+    fn describe(&self) -> NodeDescription {
+        Node::describe(self)
     }
 }
@@ -1,9 +1,10 @@
-use rand::{seq::SliceRandom, thread_rng};
+use rand::seq::SliceRandom;
 
-use crate::prelude::Node;
+use crate::prelude::{Epoch, Node, NodeDescription, NodeState};
 
 pub struct VecLoader<T> {
     elements: Vec<T>,
+    load_order: Vec<usize>, // Indexes into elements, reshuffled every epoch if shuffling
     shuffle: bool,
     current_progress: usize,
 }
@@ -11,6 +12,7 @@ pub struct VecLoader<T> {
 impl<T> VecLoader<T> {
     pub fn new(elements: Vec<T>) -> Self {
         Self {
+            load_order: (0..elements.len()).collect(),
             elements,
             shuffle: false,
             current_progress: 0,
@@ -26,9 +28,10 @@ impl<T> VecLoader<T> {
 impl<T: Clone> Node<Vec<()>> for VecLoader<T> {
     type Output = Vec<T>;
 
-    fn reset(&mut self) {
+    fn reset(&mut self, epoch: Epoch) {
+        self.load_order = (0..self.elements.len()).collect();
         if self.shuffle {
-            self.elements.shuffle(&mut thread_rng());
+            self.load_order.shuffle(&mut epoch.rng());
         }
         self.current_progress = 0;
     }
@@ -37,9 +40,11 @@ impl<T: Clone> Node<Vec<()>> for VecLoader<T> {
         if self.current_progress >= self.elements.len() {
             return vec![];
         }
-        let elements = self.elements
+        let elements = self.load_order
             [self.current_progress..(self.current_progress + input.len()).min(self.elements.len())]
-            .to_vec();
+            .iter()
+            .map(|i| self.elements[*i].clone())
+            .collect();
         self.current_progress += input.len();
         elements
     }
@@ -47,4 +52,18 @@ impl<T: Clone> Node<Vec<()>> for VecLoader<T> {
     fn data_remaining(&self, _: usize) -> usize {
         self.elements.len().saturating_sub(self.current_progress)
     }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.current_progress)
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("VecLoader")
+            .param("elements", self.elements.len())
+            .param("shuffle", self.shuffle)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.current_progress = state.index();
+    }
 }
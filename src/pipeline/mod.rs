@@ -1,11 +1,23 @@
 mod node;
 pub use node::*;
+mod describe;
+pub use describe::*;
+mod boxed;
+pub use boxed::*;
 mod premade;
 pub use premade::*;
 mod loader;
 pub use loader::*;
 mod connectors;
 pub use connectors::*;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "config")]
+pub use config::*;
+#[cfg(feature = "async")]
+mod async_node;
+#[cfg(feature = "async")]
+pub use async_node::*;
 
 #[cfg(test)]
 mod tests;
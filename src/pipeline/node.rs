@@ -1,4 +1,179 @@
-use super::{Duplicator, Pair};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{ConcurrentPair, Duplicator, Lift, NodeDescription, Pair, Profile};
+
+/// The epoch a pipeline is being reset for. Random nodes seed themselves from it, so a given seed and epoch always give the same results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Epoch {
+    /// Index of the epoch that is about to start, counting from 0
+    pub index: usize,
+    /// Pipeline wide seed
+    pub seed: u64,
+}
+
+impl Epoch {
+    pub fn new(index: usize, seed: u64) -> Self {
+        Epoch { index, seed }
+    }
+
+    /// The epoch after this one, with the same seed
+    pub fn next(self) -> Self {
+        Epoch {
+            index: self.index + 1,
+            ..self
+        }
+    }
+
+    /// The epoch as seen by a child node at some position, so sibling nodes get independent randomness
+    pub fn child(self, position: u64) -> Self {
+        Epoch {
+            seed: mix(self.seed, position),
+            ..self
+        }
+    }
+
+    /// An rng seeded from the seed and epoch index
+    pub fn rng(self) -> StdRng {
+        StdRng::seed_from_u64(mix(self.seed, self.index as u64))
+    }
+}
+
+/// A saved position in a node, made of plain values so it can be serialized.
+/// States are relative to the epoch the node was reset with, so a node is reset with the same epoch before the state is loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeState {
+    /// The node has no position to save
+    #[default]
+    Empty,
+    Index(usize),
+    Bytes(Vec<u8>),
+    /// States of child nodes, in order
+    List(Vec<NodeState>),
+}
+
+impl NodeState {
+    /// Get the index out of an Index state, panicking if the state is something else
+    pub fn index(&self) -> usize {
+        match self {
+            NodeState::Index(i) => *i,
+            s => panic!("Expected an index state, got {s:?}"),
+        }
+    }
+
+    /// Get the bytes out of a Bytes state, panicking if the state is something else
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            NodeState::Bytes(b) => b,
+            s => panic!("Expected a bytes state, got {s:?}"),
+        }
+    }
+
+    /// Get the child states out of a List state, panicking if the state is something else
+    pub fn list(&self) -> &[NodeState] {
+        match self {
+            NodeState::List(l) => l,
+            s => panic!("Expected a list state, got {s:?}"),
+        }
+    }
+}
+
+/// Bounds on the number of examples a node has left. An upper bound of None means the node can't say how many it will produce
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SizeHint {
+    pub lower: usize,
+    pub upper: Option<usize>,
+}
+
+impl SizeHint {
+    /// Exactly n examples left
+    pub fn exact(n: usize) -> Self {
+        SizeHint {
+            lower: n,
+            upper: Some(n),
+        }
+    }
+
+    /// Nothing is known about the number of examples left
+    pub fn unknown() -> Self {
+        SizeHint {
+            lower: 0,
+            upper: None,
+        }
+    }
+
+    /// Whether the bounds meet, so the number isn't an estimate
+    pub fn is_exact(&self) -> bool {
+        self.upper == Some(self.lower)
+    }
+
+    /// Whether there are definitely no examples left
+    pub fn is_empty(&self) -> bool {
+        self.upper == Some(0)
+    }
+
+    /// Map both bounds through a count. A missing upper bound goes in as usize::MAX, and comes back missing if it's still usize::MAX
+    pub fn map(self, f: impl Fn(usize) -> usize) -> Self {
+        let upper = f(self.upper.unwrap_or(usize::MAX));
+        SizeHint {
+            lower: f(self.lower),
+            upper: (upper != usize::MAX).then_some(upper),
+        }
+    }
+
+    /// Keep the upper bound but drop the lower one, for nodes that may remove examples
+    pub fn at_most(self) -> Self {
+        SizeHint { lower: 0, ..self }
+    }
+
+    /// For nodes that can turn any input into any number of outputs. Only an empty input is known to give an empty output
+    pub fn any(self) -> Self {
+        if self.is_empty() {
+            SizeHint::exact(0)
+        } else {
+            SizeHint::unknown()
+        }
+    }
+
+    /// The bounds of the smaller of two counts
+    pub fn min(self, other: Self) -> Self {
+        SizeHint {
+            lower: self.lower.min(other.lower),
+            upper: match (self.upper, other.upper) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+impl std::ops::Add for SizeHint {
+    type Output = SizeHint;
+
+    fn add(self, other: Self) -> Self {
+        SizeHint {
+            lower: self.lower.saturating_add(other.lower),
+            upper: self
+                .upper
+                .zip(other.upper)
+                .and_then(|(a, b)| a.checked_add(b)),
+        }
+    }
+}
+
+impl std::iter::Sum for SizeHint {
+    fn sum<I: Iterator<Item = SizeHint>>(iter: I) -> Self {
+        iter.fold(SizeHint::exact(0), |a, b| a + b)
+    }
+}
+
+/// Combine two numbers into a well distributed seed (splitmix64)
+fn mix(a: u64, b: u64) -> u64 {
+    let mut z = a ^ b.wrapping_add(0x9E3779B97F4A7C15).rotate_left(32);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
 
 pub trait Node<Input> {
     type Output;
@@ -6,11 +181,29 @@ pub trait Node<Input> {
     /// Process a batch of data
     fn process(&mut self, input: Input) -> Self::Output;
     /// Reset signal propogates through pipeline
-    fn reset(&mut self) {}
+    fn reset(&mut self, _epoch: Epoch) {}
     /// Get number of examples left
     fn data_remaining(&self, before: usize) -> usize {
         before
     } // Defaults to same as previous remaining data
+    /// Bounds on the number of examples left, given the bounds of the node before. Defaults to mapping both bounds through data_remaining
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.map(|b| self.data_remaining(b))
+    }
+    /// Save the node's position in the current epoch
+    fn state_dict(&self) -> NodeState {
+        NodeState::Empty
+    }
+    /// Restore a position saved with state_dict. The node should be reset with the same epoch first
+    fn load_state_dict(&mut self, _state: &NodeState) {}
+    /// Describe the node and its children, for logging the pipeline's shape
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::of::<Self>()
+    }
+    /// Handles to the stats of profiled stages in this node
+    fn profiles(&self) -> Vec<Profile> {
+        vec![]
+    }
 }
 
 impl<I, O, F: FnMut(I) -> O> Node<I> for F {
@@ -20,6 +213,47 @@ impl<I, O, F: FnMut(I) -> O> Node<I> for F {
     }
 }
 
+/// A node whose processing can fail. Errors short-circuit through chained TryNodes.
+pub trait TryNode<Input> {
+    type Output;
+    type Error;
+
+    /// Process a batch of data, or fail
+    fn try_process(&mut self, input: Input) -> Result<Self::Output, Self::Error>;
+    /// Reset signal propogates through pipeline
+    fn reset(&mut self, _epoch: Epoch) {}
+    /// Get number of examples left
+    fn data_remaining(&self, before: usize) -> usize {
+        before
+    } // Defaults to same as previous remaining data
+    /// Bounds on the number of examples left, given the bounds of the node before. Defaults to mapping both bounds through data_remaining
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.map(|b| self.data_remaining(b))
+    }
+    /// Save the node's position in the current epoch
+    fn state_dict(&self) -> NodeState {
+        NodeState::Empty
+    }
+    /// Restore a position saved with state_dict. The node should be reset with the same epoch first
+    fn load_state_dict(&mut self, _state: &NodeState) {}
+    /// Describe the node and its children, for logging the pipeline's shape
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::of::<Self>()
+    }
+    /// Handles to the stats of profiled stages in this node
+    fn profiles(&self) -> Vec<Profile> {
+        vec![]
+    }
+}
+
+impl<I, O, E, F: FnMut(I) -> Result<O, E>> TryNode<I> for F {
+    type Output = O;
+    type Error = E;
+    fn try_process(&mut self, input: I) -> Result<Self::Output, Self::Error> {
+        (self)(input)
+    }
+}
+
 pub trait ExtendNode<Input, Output, E: Node<Input, Output = Output>> {
     fn chain<O, N: Node<Output, Output = O>>(self, node: N) -> (E, N);
 }
@@ -33,6 +267,25 @@ impl<Input, Output, E: Node<Input, Output = Output>> ExtendNode<Input, Output, E
     }
 }
 
+pub trait ExtendTryNode<Input, Output, Error, E: TryNode<Input, Output = Output, Error = Error>> {
+    /// Chain a fallible node, the first error stops the chain
+    fn try_chain<O, N: TryNode<Output, Output = O>>(self, node: N) -> (E, N);
+    /// Chain an infallible node onto a fallible one
+    fn then<O, N: Node<Output, Output = O>>(self, node: N) -> (E, Lift<N, Error>);
+}
+
+impl<Input, Output, Error, E: TryNode<Input, Output = Output, Error = Error>>
+    ExtendTryNode<Input, Output, Error, E> for E
+{
+    fn try_chain<O, N: TryNode<Output, Output = O>>(self, node: N) -> (E, N) {
+        (self, node)
+    }
+
+    fn then<O, N: Node<Output, Output = O>>(self, node: N) -> (E, Lift<N, Error>) {
+        (self, Lift::new(node))
+    }
+}
+
 pub trait ExtendNodeSplit<Input, Output: Clone, E: Node<Input, Output = Output>> {
     #[allow(clippy::type_complexity)]
     fn split<O1, O2, E1: Node<Output, Output = O1>, E2: Node<Output, Output = O2>>(
@@ -40,6 +293,17 @@ pub trait ExtendNodeSplit<Input, Output: Clone, E: Node<Input, Output = Output>>
         node1: E1,
         node2: E2,
     ) -> (E, Duplicator<Output>, Pair<Output, Output, E1, E2>);
+    /// Split into two branches that run concurrently
+    #[allow(clippy::type_complexity)]
+    fn split_concurrent<O1, O2, E1: Node<Output, Output = O1>, E2: Node<Output, Output = O2>>(
+        self,
+        node1: E1,
+        node2: E2,
+    ) -> (
+        E,
+        Duplicator<Output>,
+        ConcurrentPair<Output, Output, E1, E2>,
+    );
 }
 
 impl<Input, Output: Clone, E: Node<Input, Output = Output>> ExtendNodeSplit<Input, Output, E>
@@ -53,6 +317,23 @@ impl<Input, Output: Clone, E: Node<Input, Output = Output>> ExtendNodeSplit<Inpu
     ) -> (E, Duplicator<Output>, Pair<Output, Output, E1, E2>) {
         (self, Duplicator::default(), Pair::new(node1, node2))
     }
+
+    #[allow(clippy::type_complexity)]
+    fn split_concurrent<O1, O2, E1: Node<Output, Output = O1>, E2: Node<Output, Output = O2>>(
+        self,
+        node1: E1,
+        node2: E2,
+    ) -> (
+        E,
+        Duplicator<Output>,
+        ConcurrentPair<Output, Output, E1, E2>,
+    ) {
+        (
+            self,
+            Duplicator::default(),
+            ConcurrentPair::new(node1, node2),
+        )
+    }
 }
 
 pub trait ExtendNodePair<Input, Out1, Out2, E: Node<Input, Output = (Out1, Out2)>> {
@@ -62,6 +343,13 @@ pub trait ExtendNodePair<Input, Out1, Out2, E: Node<Input, Output = (Out1, Out2)
         node1: N1,
         node2: N2,
     ) -> (E, Pair<Out1, Out2, N1, N2>);
+    /// Pair two branches that run concurrently
+    #[allow(clippy::type_complexity)]
+    fn pair_concurrent<F1, F2, N1: Node<Out1, Output = F1>, N2: Node<Out2, Output = F2>>(
+        self,
+        node1: N1,
+        node2: N2,
+    ) -> (E, ConcurrentPair<Out1, Out2, N1, N2>);
 }
 
 impl<Input, Out1, Out2, E: Node<Input, Output = (Out1, Out2)>> ExtendNodePair<Input, Out1, Out2, E>
@@ -74,6 +362,14 @@ impl<Input, Out1, Out2, E: Node<Input, Output = (Out1, Out2)>> ExtendNodePair<In
     ) -> (E, Pair<Out1, Out2, N1, N2>) {
         (self, Pair::new(node1, node2))
     }
+
+    fn pair_concurrent<F1, F2, N1: Node<Out1, Output = F1>, N2: Node<Out2, Output = F2>>(
+        self,
+        node1: N1,
+        node2: N2,
+    ) -> (E, ConcurrentPair<Out1, Out2, N1, N2>) {
+        (self, ConcurrentPair::new(node1, node2))
+    }
 }
 
 /// Feed a bunch of empty types until processing is done, returns result as vector
@@ -84,7 +380,7 @@ pub trait RunNode<T> {
 impl<T, N: Node<Vec<()>, Output = Vec<T>>> RunNode<T> for N {
     fn run(mut self, block_size: usize) -> Vec<T> {
         let mut results = vec![];
-        while self.data_remaining(usize::MAX) > 0 {
+        while !self.size_hint(SizeHint::unknown()).is_empty() {
             results.append(&mut self.process(vec![(); block_size]));
         }
         results
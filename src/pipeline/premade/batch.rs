@@ -1,8 +1,10 @@
 use std::marker::PhantomData;
 
 use itertools::Itertools;
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::pipeline::Node;
+use super::held::{load_held, save_held, serde_fns, HeldFns};
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
 
 /// Create batches from examples
 #[derive(Clone, Copy)]
@@ -14,7 +16,7 @@ pub struct Batch<T> {
 impl<T> Batch<T> {
     pub fn new(batch_size: usize) -> Self {
         Batch {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             batch_size,
         }
     }
@@ -38,6 +40,18 @@ impl<T> Node<Vec<T>> for Batch<T> {
     fn data_remaining(&self, before: usize) -> usize {
         before / self.batch_size
     }
+
+    /// Each input block ends in a partial batch, so with unknown block sizes every batch could be a single example
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        SizeHint {
+            lower: before.lower.div_ceil(self.batch_size),
+            upper: before.upper,
+        }
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Batch").param("batch_size", self.batch_size)
+    }
 }
 
 /// Create batches from examples
@@ -68,4 +82,271 @@ impl<const B: usize, T> Node<Vec<T>> for ArrayBatch<B, T> {
     fn data_remaining(&self, before: usize) -> usize {
         before / B
     }
+
+    /// Partial batches are dropped at the end of every input block, so none are guaranteed
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most().map(|b| b / B)
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("ArrayBatch").param("batch_size", B)
+    }
+}
+
+/// How TokenBudgetBatch counts the tokens a batch costs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenCost {
+    /// Examples times the longest length, which is what a padded batch takes up
+    Padded,
+    /// Sum of the lengths, for packed or unpadded batches
+    Sum,
+}
+
+/// Packs the examples a source node produces into batches that stay within a token budget, so batches of short examples hold more of them.
+/// Each call pulls as many examples from the source as it was given inputs. The last batch stays open across calls until the next example doesn't fit, and the open batch and any partial lookahead window are flushed on the call that finds the source empty.
+/// An example that's over the budget on its own goes in a batch by itself.
+/// Use `with_serde_state` to save the open batch and the lookahead window in checkpoints, so a restored node makes exactly the same batches.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut batcher = VecLoader::new(vec![vec![0; 2], vec![0; 2], vec![0; 4], vec![0; 1]])
+///     .token_budget_batch(8, |s: &Vec<u32>| s.len());
+/// batcher.reset(Epoch::default());
+/// assert_eq!(batcher.process(vec![(); 4]).len(), 2);
+/// ```
+pub struct TokenBudgetBatch<N, T, F: Fn(&T) -> usize> {
+    node: N,
+    max_tokens: usize,
+    length: F,
+    cost: TokenCost,
+    max_items: Option<usize>,
+    lookahead: usize,
+    window: Vec<(usize, T)>, // Examples waiting for the lookahead window to fill, with their lengths
+    open: Vec<T>,            // The batch being packed
+    longest: usize,
+    total: usize,
+    examples_batched: usize, // Running totals for estimating the batch size
+    batches_made: usize,
+    window_fns: Option<HeldFns<(usize, T)>>,
+    open_fns: Option<HeldFns<T>>,
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T, F: Fn(&T) -> usize> TokenBudgetBatch<N, T, F> {
+    /// Batches whose padded cost stays within `max_tokens`
+    pub fn new(node: N, max_tokens: usize, length: F) -> Self {
+        TokenBudgetBatch {
+            node,
+            max_tokens,
+            length,
+            cost: TokenCost::Padded,
+            max_items: None,
+            lookahead: 1,
+            window: vec![],
+            open: vec![],
+            longest: 0,
+            total: 0,
+            examples_batched: 0,
+            batches_made: 0,
+            window_fns: None,
+            open_fns: None,
+        }
+    }
+
+    /// Save the open batch and the lookahead window in checkpoints with serde, so restoring doesn't lose them
+    pub fn with_serde_state(self) -> Self
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        TokenBudgetBatch {
+            window_fns: Some(serde_fns()),
+            open_fns: Some(serde_fns()),
+            ..self
+        }
+    }
+
+    /// How the cost of a batch is counted
+    pub fn cost(self, cost: TokenCost) -> Self {
+        TokenBudgetBatch { cost, ..self }
+    }
+
+    /// Also cap the number of examples in a batch
+    pub fn max_items(self, max_items: usize) -> Self {
+        assert!(
+            max_items > 0,
+            "TokenBudgetBatch max items must be at least 1"
+        );
+        TokenBudgetBatch {
+            max_items: Some(max_items),
+            ..self
+        }
+    }
+
+    /// Sort examples by length within windows of this many, so similar lengths get batched together
+    pub fn lookahead(self, lookahead: usize) -> Self {
+        TokenBudgetBatch {
+            lookahead: lookahead.max(1),
+            ..self
+        }
+    }
+
+    fn fits(&self, items: usize, longest: usize, total: usize) -> bool {
+        let cost = match self.cost {
+            TokenCost::Padded => items.saturating_mul(longest),
+            TokenCost::Sum => total,
+        };
+        cost <= self.max_tokens && self.max_items.map(|m| items <= m).unwrap_or(true)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.node.size_hint(SizeHint::exact(0)).is_empty()
+    }
+
+    /// Examples pulled from the source that aren't in a finished batch yet
+    fn waiting(&self) -> usize {
+        self.window.len() + self.open.len()
+    }
+
+    /// Close the open batch
+    fn close(&mut self, batches: &mut Vec<Vec<T>>) {
+        if !self.open.is_empty() {
+            self.examples_batched += self.open.len();
+            self.batches_made += 1;
+            batches.push(std::mem::take(&mut self.open));
+            (self.longest, self.total) = (0, 0);
+        }
+    }
+
+    /// Sort the first examples waiting for the lookahead, and pack them into the open batch
+    fn pack(&mut self, examples: usize, batches: &mut Vec<Vec<T>>) {
+        let mut window = self.window.drain(..examples).collect::<Vec<_>>();
+        window.sort_by_key(|(length, _)| *length);
+        for (length, example) in window {
+            if !self.open.is_empty()
+                && !self.fits(
+                    self.open.len() + 1,
+                    self.longest.max(length),
+                    self.total + length,
+                )
+            {
+                self.close(batches);
+            }
+            self.open.push(example);
+            self.longest = self.longest.max(length);
+            self.total += length;
+        }
+    }
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T, F: Fn(&T) -> usize> Node<Vec<()>>
+    for TokenBudgetBatch<N, T, F>
+{
+    type Output = Vec<Vec<T>>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        let mut batches = vec![];
+        if !self.exhausted() {
+            for example in self.node.process(input) {
+                self.window.push(((self.length)(&example), example));
+            }
+            while self.window.len() >= self.lookahead {
+                self.pack(self.lookahead, &mut batches);
+            }
+        }
+        if self.exhausted() {
+            // The source ran out, so flush the partial window and the open batch
+            self.pack(self.window.len(), &mut batches);
+            self.close(&mut batches);
+        }
+        batches
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+        self.window.clear();
+        self.open.clear();
+        (self.longest, self.total) = (0, 0);
+    }
+
+    /// Estimated from the average batch size so far
+    fn data_remaining(&self, before: usize) -> usize {
+        let examples = self
+            .node
+            .data_remaining(before)
+            .saturating_add(self.waiting());
+        match self.batches_made {
+            0 => examples,
+            made => examples
+                .saturating_mul(made)
+                .div_ceil(self.examples_batched),
+        }
+    }
+
+    /// Batches hold at least one example, and at most `max_items`
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        let examples = self.node.size_hint(before) + SizeHint::exact(self.waiting());
+        SizeHint {
+            lower: match self.max_items {
+                Some(max_items) => examples.lower.div_ceil(max_items),
+                None => examples.lower.min(1),
+            },
+            upper: examples.upper,
+        }
+    }
+
+    /// The lookahead window, the open batch, the running totals and the node's state
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(vec![
+            save_held(self.window_fns, &self.window),
+            save_held(self.open_fns, &self.open),
+            NodeState::Index(self.examples_batched),
+            NodeState::Index(self.batches_made),
+            self.node.state_dict(),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        self.window = load_held(self.window_fns, &state[0]);
+        self.open = load_held(self.open_fns, &state[1]);
+        // The open batch's cost is worked out again from its examples
+        let lengths = self.open.iter().map(&self.length).collect::<Vec<_>>();
+        self.longest = lengths.iter().copied().max().unwrap_or(0);
+        self.total = lengths.iter().sum();
+        self.examples_batched = state[2].index();
+        self.batches_made = state[3].index();
+        self.node.load_state_dict(&state[4]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("TokenBudgetBatch")
+            .param("max_tokens", self.max_tokens)
+            .param("cost", format!("{:?}", self.cost))
+            .param("lookahead", self.lookahead)
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeTokenBudgetBatch<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Pack this node's examples into batches within a token budget
+    fn token_budget_batch<F: Fn(&T) -> usize>(
+        self,
+        max_tokens: usize,
+        length: F,
+    ) -> TokenBudgetBatch<E, T, F>;
+}
+
+impl<T, E: Node<Vec<()>, Output = Vec<T>>> ExtendNodeTokenBudgetBatch<T, E> for E {
+    fn token_budget_batch<F: Fn(&T) -> usize>(
+        self,
+        max_tokens: usize,
+        length: F,
+    ) -> TokenBudgetBatch<E, T, F> {
+        TokenBudgetBatch::new(self, max_tokens, length)
+    }
 }
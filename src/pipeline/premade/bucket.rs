@@ -0,0 +1,229 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::held::{load_held, save_held, serde_fns, HeldFns};
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+
+/// How BucketBatch splits keys into buckets
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Buckets {
+    /// Ascending bucket boundaries. A key goes in the first bucket whose boundary is larger than it, or the last bucket
+    Boundaries(Vec<usize>),
+    /// This many buckets holding roughly equal numbers of examples, with boundaries taken from the keys of the first input
+    Count(usize),
+}
+
+/// Groups the examples a source node produces by similar keys (usually lengths) into batches, so padded batches waste less compute.
+/// Each call pulls as many examples from the source as it was given inputs. Examples wait in their bucket across calls, and a batch comes out as soon as a bucket fills.
+///
+/// Leftover examples come out as partial batches on the call that finds the source empty.
+/// Anything still waiting when the node is reset is dropped, so no examples carry over into the next epoch.
+/// Checkpoints save the learned boundaries, and with `with_serde_state` the waiting examples too, so a restored node makes exactly the same batches.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let texts = vec!["a", "long sentence", "b", "c"].into_iter().map(String::from).collect();
+/// let mut batcher = VecLoader::new(texts).bucket_batch(2, |s: &String| s.len(), Buckets::Boundaries(vec![5, 10]));
+/// batcher.reset(Epoch::default());
+/// assert_eq!(batcher.process(vec![(); 3]), vec![vec!["a".to_string(), "b".to_string()]]);
+/// ```
+pub struct BucketBatch<N, T, F: Fn(&T) -> usize> {
+    node: N,
+    batch_size: usize,
+    key: F,
+    buckets: Buckets,
+    boundaries: Option<Vec<usize>>, // Resolved boundaries, learned from the first input for a bucket count
+    waiting: Vec<Vec<T>>,
+    waiting_fns: Option<HeldFns<T>>,
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T, F: Fn(&T) -> usize> BucketBatch<N, T, F> {
+    pub fn new(node: N, batch_size: usize, key: F, buckets: Buckets) -> Self {
+        assert!(batch_size > 0, "BucketBatch batch size must be at least 1");
+        let boundaries = match &buckets {
+            Buckets::Boundaries(boundaries) => {
+                assert!(
+                    boundaries.windows(2).all(|w| w[0] < w[1]),
+                    "Bucket boundaries must be ascending"
+                );
+                Some(boundaries.clone())
+            }
+            Buckets::Count(count) => {
+                assert!(*count > 0, "BucketBatch needs at least 1 bucket");
+                None
+            }
+        };
+        BucketBatch {
+            node,
+            batch_size,
+            key,
+            buckets,
+            boundaries,
+            waiting: vec![],
+            waiting_fns: None,
+        }
+    }
+
+    /// Save the examples waiting in buckets in checkpoints with serde, so restoring doesn't lose them
+    pub fn with_serde_state(self) -> Self
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        BucketBatch {
+            waiting_fns: Some(serde_fns()),
+            ..self
+        }
+    }
+
+    fn bucket_count(&self) -> usize {
+        match &self.buckets {
+            Buckets::Boundaries(boundaries) => boundaries.len() + 1,
+            Buckets::Count(count) => *count,
+        }
+    }
+
+    fn waiting(&self) -> usize {
+        self.waiting.iter().map(Vec::len).sum()
+    }
+
+    fn exhausted(&self) -> bool {
+        self.node.size_hint(SizeHint::exact(0)).is_empty()
+    }
+
+    /// Boundaries splitting the keys into equally sized buckets
+    fn learn_boundaries(&self, input: &[T], count: usize) -> Vec<usize> {
+        let mut keys = input.iter().map(&self.key).collect::<Vec<_>>();
+        keys.sort_unstable();
+        let mut boundaries = (1..count)
+            .map(|i| keys[i * keys.len() / count])
+            .collect::<Vec<_>>();
+        boundaries.dedup();
+        boundaries
+    }
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T, F: Fn(&T) -> usize> Node<Vec<()>>
+    for BucketBatch<N, T, F>
+{
+    type Output = Vec<Vec<T>>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        let mut batches = vec![];
+        if !self.exhausted() {
+            let examples = self.node.process(input);
+            if self.boundaries.is_none() && !examples.is_empty() {
+                if let Buckets::Count(count) = self.buckets {
+                    self.boundaries = Some(self.learn_boundaries(&examples, count));
+                }
+            }
+            if let Some(boundaries) = &self.boundaries {
+                self.waiting.resize_with(boundaries.len() + 1, Vec::new);
+                for example in examples {
+                    let key = (self.key)(&example);
+                    let bucket = &mut self.waiting[boundaries.partition_point(|b| *b <= key)];
+                    bucket.push(example);
+                    if bucket.len() == self.batch_size {
+                        batches.push(std::mem::take(bucket));
+                    }
+                }
+            }
+        }
+        if self.exhausted() {
+            // The source ran out, so flush the partial batches
+            batches.extend(self.waiting.drain(..).filter(|b| !b.is_empty()));
+        }
+        batches
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+        self.waiting.clear();
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node
+            .data_remaining(before)
+            .saturating_add(self.waiting())
+            .div_ceil(self.batch_size)
+    }
+
+    /// Every bucket can end with a partial batch
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        let hint = self.node.size_hint(before);
+        let waiting = self.waiting();
+        let partial = self.waiting.iter().filter(|b| !b.is_empty()).count();
+        SizeHint {
+            lower: hint.lower.saturating_add(waiting).div_ceil(self.batch_size),
+            upper: hint.upper.map(|u| match u {
+                0 => partial, // Only the flush is left
+                _ => (u + waiting) / self.batch_size + self.bucket_count().min(u + waiting),
+            }),
+        }
+    }
+
+    /// The boundaries if they're known, each bucket's waiting examples, and the node's state
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(vec![
+            match &self.boundaries {
+                Some(boundaries) => {
+                    NodeState::List(boundaries.iter().map(|b| NodeState::Index(*b)).collect())
+                }
+                None => NodeState::Empty,
+            },
+            NodeState::List(
+                self.waiting
+                    .iter()
+                    .map(|bucket| save_held(self.waiting_fns, bucket))
+                    .collect(),
+            ),
+            self.node.state_dict(),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        self.boundaries = match &state[0] {
+            NodeState::Empty => None,
+            boundaries => Some(boundaries.list().iter().map(NodeState::index).collect()),
+        };
+        self.waiting = state[1]
+            .list()
+            .iter()
+            .map(|bucket| load_held(self.waiting_fns, bucket))
+            .collect();
+        self.node.load_state_dict(&state[2]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("BucketBatch")
+            .param("batch_size", self.batch_size)
+            .param("buckets", self.bucket_count())
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeBucketBatch<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Batch this node's examples, grouping them into buckets by a key
+    fn bucket_batch<F: Fn(&T) -> usize>(
+        self,
+        batch_size: usize,
+        key: F,
+        buckets: Buckets,
+    ) -> BucketBatch<E, T, F>;
+}
+
+impl<T, E: Node<Vec<()>, Output = Vec<T>>> ExtendNodeBucketBatch<T, E> for E {
+    fn bucket_batch<F: Fn(&T) -> usize>(
+        self,
+        batch_size: usize,
+        key: F,
+        buckets: Buckets,
+    ) -> BucketBatch<E, T, F> {
+        BucketBatch::new(self, batch_size, key, buckets)
+    }
+}
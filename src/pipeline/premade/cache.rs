@@ -0,0 +1,391 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use rand::seq::SliceRandom;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+
+/// Where a Cache keeps the examples it recorded
+pub trait CacheStorage<T> {
+    /// Whether a complete recording made with this fingerprint is stored
+    fn is_complete(&mut self, fingerprint: &str) -> bool;
+    /// Throw away everything stored
+    fn clear(&mut self);
+    fn record(&mut self, items: &[T]);
+    /// Mark the recording as complete
+    fn finish(&mut self, fingerprint: &str);
+    /// Number of examples stored
+    fn count(&self) -> usize;
+    fn get(&mut self, index: usize) -> T;
+}
+
+/// Keeps cached examples in memory
+pub struct MemoryCache<T> {
+    items: Vec<T>,
+    fingerprint: Option<String>, // Set once the recording is complete
+}
+
+impl<T> Default for MemoryCache<T> {
+    fn default() -> Self {
+        MemoryCache {
+            items: vec![],
+            fingerprint: None,
+        }
+    }
+}
+
+impl<T: Clone> CacheStorage<T> for MemoryCache<T> {
+    fn is_complete(&mut self, fingerprint: &str) -> bool {
+        self.fingerprint.as_deref() == Some(fingerprint)
+    }
+
+    fn clear(&mut self) {
+        self.items.clear();
+        self.fingerprint = None;
+    }
+
+    fn record(&mut self, items: &[T]) {
+        self.items.extend_from_slice(items);
+    }
+
+    fn finish(&mut self, fingerprint: &str) {
+        self.fingerprint = Some(fingerprint.to_string());
+    }
+
+    fn count(&self) -> usize {
+        self.items.len()
+    }
+
+    fn get(&mut self, index: usize) -> T {
+        self.items[index].clone()
+    }
+}
+
+/// Spills cached examples to a directory, so they don't take up memory and can be reused by later runs.
+/// Examples go in `data.bin`, and `meta.bin` is written once the recording is complete
+pub struct DiskCache<T> {
+    dir: PathBuf,
+    offsets: Vec<u64>, // Where each example starts in the data file
+    position: u64,     // End of the data written so far
+    writer: Option<BufWriter<File>>,
+    reader: Option<(BufReader<File>, usize)>, // The data file and the index of the example it's positioned at
+    fingerprint: Option<String>,
+    _phantom: PhantomData<fn(T) -> T>,
+}
+
+impl<T> DiskCache<T> {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        fs::create_dir_all(&dir).expect("Failed to create the cache directory");
+        DiskCache {
+            dir: dir.as_ref().to_path_buf(),
+            offsets: vec![],
+            position: 0,
+            writer: None,
+            reader: None,
+            fingerprint: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn data_path(&self) -> PathBuf {
+        self.dir.join("data.bin")
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.dir.join("meta.bin")
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> CacheStorage<T> for DiskCache<T> {
+    fn is_complete(&mut self, fingerprint: &str) -> bool {
+        if self.fingerprint.is_none() && self.writer.is_none() {
+            // A previous run may have left a complete recording behind
+            if let Ok((stored, offsets)) = File::open(self.meta_path())
+                .map_err(bincode::Error::from)
+                .and_then(|f| bincode::deserialize_from::<_, (String, Vec<u64>)>(BufReader::new(f)))
+            {
+                self.fingerprint = Some(stored);
+                self.offsets = offsets;
+            }
+        }
+        self.fingerprint.as_deref() == Some(fingerprint)
+    }
+
+    fn clear(&mut self) {
+        self.offsets.clear();
+        self.position = 0;
+        self.writer = None;
+        self.reader = None;
+        self.fingerprint = None;
+        let _ = fs::remove_file(self.meta_path());
+        let _ = fs::remove_file(self.data_path());
+    }
+
+    fn record(&mut self, items: &[T]) {
+        if self.writer.is_none() {
+            // Starting a new recording, so anything stored before is stale
+            self.clear();
+            let file = File::create(self.data_path()).expect("Failed to create the cache file");
+            self.writer = Some(BufWriter::new(file));
+        }
+        let writer = self.writer.as_mut().unwrap();
+        for item in items {
+            let bytes = bincode::serialize(item).expect("Failed to serialize a cached example");
+            writer
+                .write_all(&bytes)
+                .expect("Failed to write to the cache file");
+            self.offsets.push(self.position);
+            self.position += bytes.len() as u64;
+        }
+    }
+
+    fn finish(&mut self, fingerprint: &str) {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush().expect("Failed to write to the cache file");
+        } else {
+            File::create(self.data_path()).expect("Failed to create the cache file");
+        }
+        let meta = bincode::serialize(&(fingerprint, &self.offsets)).unwrap();
+        fs::write(self.meta_path(), meta).expect("Failed to write the cache metadata");
+        self.fingerprint = Some(fingerprint.to_string());
+    }
+
+    fn count(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn get(&mut self, index: usize) -> T {
+        let (reader, next) = match &mut self.reader {
+            Some(reader) => reader,
+            None => {
+                let file = File::open(self.data_path()).expect("Failed to open the cache file");
+                self.reader.insert((BufReader::new(file), usize::MAX))
+            }
+        };
+        // Reading in order needs no seeking
+        if *next != index {
+            reader
+                .seek(SeekFrom::Start(self.offsets[index]))
+                .expect("Failed to read the cache file");
+        }
+        *next = index + 1;
+        bincode::deserialize_from(reader).expect("Failed to read a cached example")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Running the node and recording its output
+    Recording,
+    /// Running the node without recording, because the recording finished or was resumed partway through
+    Passing,
+    /// Replaying a complete recording instead of running the node
+    Replaying,
+}
+
+/// Records everything the node produces in its first epoch, and replays it in later epochs instead of running the node again.
+/// The node must be a deterministic, expensive pipeline driven by a source, since inputs are only used for their length while replaying,
+/// and the recording only counts as complete once the source reports it ran out.
+/// The recording is committed when the cache is next reset, so an epoch cut short is recorded again rather than replayed partially.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let tokenized = Cache::new(
+///     VecLoader::new(vec!["Hello World".to_string()])
+///         .map(|s: String| s.split(' ').map(|s| s.to_string()).collect::<Vec<_>>()),
+/// )
+/// .reshuffle(true)
+/// .fingerprint(|| "tokenizer-v1".to_string());
+/// ```
+pub struct Cache<I, T, N, S = MemoryCache<T>> {
+    node: N,
+    storage: S,
+    fingerprint: Option<Box<dyn Fn() -> String + Send>>,
+    reshuffle: bool,
+    mode: Mode,
+    epoch: Epoch,
+    order: Vec<usize>, // Indexes into the recording, in the order they are replayed this epoch
+    progress: usize,
+    recorded: bool, // The node ran while recording since the last reset
+    _phantom: PhantomData<fn(I) -> T>,
+}
+
+impl<I, T, N: Node<Vec<I>, Output = Vec<T>>> Cache<I, T, N> {
+    /// Cache the node's output in memory
+    pub fn new(node: N) -> Self {
+        Cache {
+            node,
+            storage: MemoryCache::default(),
+            fingerprint: None,
+            reshuffle: false,
+            mode: Mode::Recording,
+            epoch: Epoch::default(),
+            order: vec![],
+            progress: 0,
+            recorded: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Cache the node's output in a directory instead of memory. A complete cache left there by a previous run is reused if the fingerprint matches,
+    /// so the fingerprint should change whenever the node would produce something different
+    pub fn on_disk<P: AsRef<Path>>(
+        self,
+        dir: P,
+        fingerprint: impl Fn() -> String + Send + 'static,
+    ) -> Cache<I, T, N, DiskCache<T>> {
+        Cache {
+            node: self.node,
+            storage: DiskCache::new(dir),
+            fingerprint: Some(Box::new(fingerprint)),
+            reshuffle: self.reshuffle,
+            mode: self.mode,
+            epoch: self.epoch,
+            order: self.order,
+            progress: self.progress,
+            recorded: self.recorded,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<I, T, N, S> Cache<I, T, N, S> {
+    /// Shuffle the cached examples every epoch they are replayed
+    pub fn reshuffle(self, reshuffle: bool) -> Self {
+        Cache { reshuffle, ..self }
+    }
+
+    /// The cache is thrown away and recorded again whenever this returns something different, checked on every reset
+    pub fn fingerprint(self, fingerprint: impl Fn() -> String + Send + 'static) -> Self {
+        Cache {
+            fingerprint: Some(Box::new(fingerprint)),
+            ..self
+        }
+    }
+
+    fn current_fingerprint(&self) -> String {
+        self.fingerprint.as_ref().map(|f| f()).unwrap_or_default()
+    }
+}
+
+impl<I, T, N: Node<Vec<I>, Output = Vec<T>>, S: CacheStorage<T>> Node<Vec<I>>
+    for Cache<I, T, N, S>
+{
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<I>) -> Self::Output {
+        match self.mode {
+            Mode::Replaying => {
+                let end = (self.progress + input.len()).min(self.order.len());
+                let items = self.order[self.progress..end]
+                    .iter()
+                    .map(|i| self.storage.get(*i))
+                    .collect();
+                self.progress = end;
+                items
+            }
+            Mode::Recording => {
+                let output = self.node.process(input);
+                self.storage.record(&output);
+                self.recorded = true;
+                output
+            }
+            Mode::Passing => self.node.process(input),
+        }
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.epoch = epoch;
+        let fingerprint = self.current_fingerprint();
+        if self.mode == Mode::Recording
+            && self.recorded
+            && self.node.size_hint(SizeHint::exact(0)).is_empty()
+        {
+            // The last epoch ran the source dry, so everything it produced was recorded
+            self.storage.finish(&fingerprint);
+        }
+        self.recorded = false;
+        if self.storage.is_complete(&fingerprint) {
+            self.mode = Mode::Replaying;
+            self.order = (0..self.storage.count()).collect();
+            if self.reshuffle {
+                self.order.shuffle(&mut epoch.rng());
+            }
+            self.progress = 0;
+        } else {
+            self.storage.clear();
+            self.node.reset(epoch);
+            self.mode = Mode::Recording;
+        }
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        match self.mode {
+            Mode::Replaying => self.order.len() - self.progress,
+            _ => self.node.data_remaining(before),
+        }
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        match self.mode {
+            Mode::Replaying => SizeHint::exact(self.order.len() - self.progress),
+            _ => self.node.size_hint(before),
+        }
+    }
+
+    fn state_dict(&self) -> NodeState {
+        match self.mode {
+            Mode::Replaying => {
+                NodeState::List(vec![NodeState::Index(1), NodeState::Index(self.progress)])
+            }
+            _ => NodeState::List(vec![NodeState::Index(0), self.node.state_dict()]),
+        }
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        if state[0].index() == 1 {
+            assert!(
+                self.mode == Mode::Replaying,
+                "The cache was replaying when this state was saved, but it is empty now. Use a disk cache to resume across runs"
+            );
+            self.progress = state[1].index();
+        } else {
+            if self.mode == Mode::Replaying {
+                // The state was saved while recording, so run the node again from where it was
+                self.node.reset(self.epoch);
+            }
+            // Part of the epoch was recorded by someone else, so this recording would be incomplete
+            self.mode = Mode::Passing;
+            self.node.load_state_dict(&state[1]);
+        }
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Cache")
+            .param("reshuffle", self.reshuffle)
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeCache<I, T, E: Node<Vec<I>, Output = Vec<T>>> {
+    /// Cache this node's output in memory after the first epoch
+    fn cache(self) -> Cache<I, T, E>;
+}
+
+impl<I, T, E: Node<Vec<I>, Output = Vec<T>>> ExtendNodeCache<I, T, E> for E {
+    fn cache(self) -> Cache<I, T, E> {
+        Cache::new(self)
+    }
+}
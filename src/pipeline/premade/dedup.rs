@@ -0,0 +1,368 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, SizeHint};
+
+fn hash<H: Hash + ?Sized>(value: &H) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Drops examples whose key was already seen this epoch. Keys are stored whole, so distinct keys are never confused, and the seen set is cleared on reset.
+/// The seen keys are only saved in checkpoints with `with_serde_state`, otherwise a restored node lets through keys seen before the checkpoint. For near duplicates, use NearDedup.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut dedup = Dedup::new(|s: &String| s.to_lowercase());
+/// assert_eq!(dedup.process(vec!["a".to_string(), "A".to_string()]), vec!["a".to_string()]);
+/// assert_eq!(dedup.process(vec!["a".to_string(), "b".to_string()]), vec!["b".to_string()]);
+/// ```
+pub struct Dedup<T, K: Hash + Eq, F: Fn(&T) -> K> {
+    key: F,
+    seen: HashSet<K>,
+    seen_fns: Option<SeenFns<K>>,
+    _phantom: PhantomData<fn(T)>,
+}
+
+/// Functions to save and load the seen keys when checkpointing
+type SeenFns<K> = (fn(&HashSet<K>) -> NodeState, fn(&NodeState) -> HashSet<K>);
+
+fn save_seen<K: Serialize>(seen: &HashSet<K>) -> NodeState {
+    NodeState::Bytes(bincode::serialize(seen).expect("Failed to serialize the seen keys"))
+}
+
+fn load_seen<K: Hash + Eq + DeserializeOwned>(saved: &NodeState) -> HashSet<K> {
+    bincode::deserialize(saved.bytes()).expect("Failed to deserialize the seen keys")
+}
+
+impl<T, K: Hash + Eq, F: Fn(&T) -> K> Dedup<T, K, F> {
+    pub fn new(key: F) -> Self {
+        Dedup {
+            key,
+            seen: HashSet::new(),
+            seen_fns: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Save the seen keys in checkpoints with serde, so a restored node still drops keys seen before the checkpoint
+    pub fn with_serde_state(self) -> Self
+    where
+        K: Serialize + DeserializeOwned,
+    {
+        Dedup {
+            seen_fns: Some((save_seen::<K>, load_seen::<K>)),
+            ..self
+        }
+    }
+}
+
+impl<T, K: Hash + Eq, F: Fn(&T) -> K> Node<Vec<T>> for Dedup<T, K, F> {
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<T>) -> Self::Output {
+        input
+            .into_iter()
+            .filter(|example| self.seen.insert((self.key)(example)))
+            .collect()
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        self.seen.clear();
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most()
+    }
+
+    fn state_dict(&self) -> NodeState {
+        match self.seen_fns {
+            Some((save, _)) => save(&self.seen),
+            None => NodeState::Empty,
+        }
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.seen = match self.seen_fns {
+            Some((_, load)) => load(state),
+            None => HashSet::new(),
+        };
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Dedup")
+    }
+}
+
+/// Examples NearDedup found to be near duplicates of each other. Examples are numbered by the order they reached the node this epoch, counting from 0
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuplicateCluster {
+    /// The example that was kept
+    pub kept: usize,
+    /// The examples dropped as its duplicates
+    pub duplicates: Vec<usize>,
+}
+
+/// Drops examples whose text is a near duplicate of one already kept this epoch, using MinHash signatures and locality sensitive hashing.
+/// Texts are split into shingles of `shingle_size` words, and the signature is split into `bands` bands of `rows` hashes each.
+/// Examples sharing a band are compared, and count as duplicates when their estimated Jaccard similarity is at least the threshold.
+/// Checkpoints save the signatures of kept examples and the recorded clusters, so a restored node still drops near duplicates of what it already kept.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut dedup = NearDedup::new(|s: &String| s.as_str())
+///     .shingle_size(2)
+///     .bands(32)
+///     .rows(4)
+///     .threshold(0.5);
+/// let texts = vec![
+///     "the quick brown fox jumps over the lazy dog".to_string(),
+///     "the quick brown fox jumps over the lazy cat".to_string(),
+///     "a completely different sentence about something else".to_string(),
+/// ];
+/// assert_eq!(dedup.process(texts).len(), 2);
+/// ```
+pub struct NearDedup<T, F: Fn(&T) -> &str> {
+    text: F,
+    shingle_size: usize,
+    bands: usize,
+    rows: usize,
+    threshold: f64,
+    permutations: Vec<(u64, u64)>, // Multiplier and offset of each hash function in the signature
+    signatures: Vec<Vec<u64>>,     // Signatures of the kept examples
+    kept: Vec<usize>,              // Stream position of each kept example
+    buckets: HashMap<(usize, u64), Vec<usize>>, // Kept examples sharing each band
+    seen: usize,
+    clusters: Option<Arc<Mutex<Vec<DuplicateCluster>>>>,
+    cluster_of: HashMap<usize, usize>, // Cluster index of each kept example that has duplicates
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T, F: Fn(&T) -> &str> NearDedup<T, F> {
+    /// Deduplicate by the text this returns, with 5 word shingles, 16 bands of 8 rows and a threshold of 0.8
+    pub fn new(text: F) -> Self {
+        NearDedup {
+            text,
+            shingle_size: 5,
+            bands: 16,
+            rows: 8,
+            threshold: 0.8,
+            permutations: permutations(16 * 8),
+            signatures: vec![],
+            kept: vec![],
+            buckets: HashMap::new(),
+            seen: 0,
+            clusters: None,
+            cluster_of: HashMap::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Number of words in each shingle
+    pub fn shingle_size(self, shingle_size: usize) -> Self {
+        NearDedup {
+            shingle_size: shingle_size.max(1),
+            ..self
+        }
+    }
+
+    /// Split the signature into this many bands. More bands find more candidates, at the cost of more comparisons
+    pub fn bands(self, bands: usize) -> Self {
+        let bands = bands.max(1);
+        NearDedup {
+            bands,
+            permutations: permutations(bands * self.rows),
+            ..self
+        }
+    }
+
+    /// Number of hashes in each band. More rows make candidates need a higher similarity to share a band
+    pub fn rows(self, rows: usize) -> Self {
+        let rows = rows.max(1);
+        NearDedup {
+            rows,
+            permutations: permutations(self.bands * rows),
+            ..self
+        }
+    }
+
+    /// The estimated Jaccard similarity at which examples count as duplicates
+    pub fn threshold(self, threshold: f64) -> Self {
+        NearDedup { threshold, ..self }
+    }
+
+    /// Record the duplicate clusters found each epoch, for auditing what was dropped
+    pub fn record_clusters(self) -> Self {
+        NearDedup {
+            clusters: Some(Arc::default()),
+            ..self
+        }
+    }
+
+    /// A handle to the clusters found so far this epoch. Empty unless `record_clusters` was set
+    pub fn clusters(&self) -> Arc<Mutex<Vec<DuplicateCluster>>> {
+        self.clusters.clone().unwrap_or_default()
+    }
+
+    fn signature(&self, text: &str) -> Vec<u64> {
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        let shingles = match words.len() > self.shingle_size {
+            true => words.windows(self.shingle_size).map(hash).collect(),
+            false => vec![hash(&words)],
+        };
+        self.permutations
+            .iter()
+            .map(|(a, b)| {
+                shingles
+                    .iter()
+                    .map(|s| s.wrapping_mul(*a).wrapping_add(*b).rotate_left(29))
+                    .min()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn band_keys(&self, signature: &[u64]) -> Vec<(usize, u64)> {
+        signature
+            .chunks(self.rows)
+            .enumerate()
+            .map(|(band, rows)| (band, hash(rows)))
+            .collect()
+    }
+
+    /// The first kept example this signature is a near duplicate of
+    fn find_duplicate(&self, signature: &[u64], band_keys: &[(usize, u64)]) -> Option<usize> {
+        band_keys
+            .iter()
+            .filter_map(|key| self.buckets.get(key))
+            .flatten()
+            .copied()
+            .find(|candidate| {
+                let other = &self.signatures[*candidate];
+                let same = signature.iter().zip(other).filter(|(a, b)| a == b).count();
+                same as f64 / signature.len() as f64 >= self.threshold
+            })
+    }
+
+    /// Index a kept example's signature under its bands
+    fn keep(&mut self, signature: Vec<u64>, band_keys: Vec<(usize, u64)>, position: usize) {
+        let index = self.signatures.len();
+        for key in band_keys {
+            self.buckets.entry(key).or_default().push(index);
+        }
+        self.signatures.push(signature);
+        self.kept.push(position);
+    }
+
+    fn record(&mut self, kept: usize, duplicate: usize) {
+        if let Some(clusters) = &self.clusters {
+            let mut clusters = clusters.lock().unwrap();
+            let index = *self.cluster_of.entry(kept).or_insert_with(|| {
+                clusters.push(DuplicateCluster {
+                    kept,
+                    duplicates: vec![],
+                });
+                clusters.len() - 1
+            });
+            clusters[index].duplicates.push(duplicate);
+        }
+    }
+}
+
+/// Random odd multipliers and offsets for the MinHash functions, the same every run
+fn permutations(count: usize) -> Vec<(u64, u64)> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| (rng.gen::<u64>() | 1, rng.gen()))
+        .collect()
+}
+
+impl<T, F: Fn(&T) -> &str> Node<Vec<T>> for NearDedup<T, F> {
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<T>) -> Self::Output {
+        let mut output = Vec::with_capacity(input.len());
+        for example in input {
+            let position = self.seen;
+            self.seen += 1;
+            let signature = self.signature((self.text)(&example));
+            let band_keys = self.band_keys(&signature);
+            match self.find_duplicate(&signature, &band_keys) {
+                Some(duplicate_of) => self.record(self.kept[duplicate_of], position),
+                None => {
+                    self.keep(signature, band_keys, position);
+                    output.push(example);
+                }
+            }
+        }
+        output
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        self.signatures.clear();
+        self.kept.clear();
+        self.buckets.clear();
+        self.seen = 0;
+        self.cluster_of.clear();
+        if let Some(clusters) = &self.clusters {
+            clusters.lock().unwrap().clear();
+        }
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most()
+    }
+
+    /// The examples seen, the kept signatures and positions, and the recorded clusters. The band buckets are rebuilt from the signatures
+    fn state_dict(&self) -> NodeState {
+        let clusters = self.clusters.as_ref().map(|c| c.lock().unwrap().clone());
+        NodeState::Bytes(
+            bincode::serialize(&(self.seen, &self.kept, &self.signatures, clusters))
+                .expect("Failed to serialize the signatures"),
+        )
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        type Saved = (usize, Vec<usize>, Vec<Vec<u64>>, Option<Vec<DuplicateCluster>>);
+        let (seen, kept, signatures, clusters): Saved =
+            bincode::deserialize(state.bytes()).expect("Failed to deserialize the signatures");
+        self.signatures.clear();
+        self.kept.clear();
+        self.buckets.clear();
+        for (signature, position) in signatures.into_iter().zip(kept) {
+            let band_keys = self.band_keys(&signature);
+            self.keep(signature, band_keys, position);
+        }
+        self.seen = seen;
+        self.cluster_of.clear();
+        if let Some(handle) = &self.clusters {
+            let clusters = clusters.unwrap_or_default();
+            self.cluster_of = clusters
+                .iter()
+                .enumerate()
+                .map(|(index, cluster)| (cluster.kept, index))
+                .collect();
+            *handle.lock().unwrap() = clusters;
+        }
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("NearDedup")
+            .param("shingle_size", self.shingle_size)
+            .param("bands", self.bands)
+            .param("rows", self.rows)
+            .param("threshold", self.threshold)
+    }
+}
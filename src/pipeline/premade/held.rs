@@ -0,0 +1,35 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::pipeline::NodeState;
+
+/// Functions to save and load the examples a node holds between calls when checkpointing, set by the node's `with_serde_state`
+pub(crate) type HeldFns<T> = (fn(&[T]) -> NodeState, fn(&NodeState) -> Vec<T>);
+
+/// Save and load held examples with serde
+pub(crate) fn serde_fns<T: Serialize + DeserializeOwned>() -> HeldFns<T> {
+    (save_serde::<T>, load_serde::<T>)
+}
+
+fn save_serde<T: Serialize>(held: &[T]) -> NodeState {
+    NodeState::Bytes(bincode::serialize(held).expect("Failed to serialize the held examples"))
+}
+
+fn load_serde<T: DeserializeOwned>(saved: &NodeState) -> Vec<T> {
+    bincode::deserialize(saved.bytes()).expect("Failed to deserialize the held examples")
+}
+
+/// The saved examples, or nothing if the node wasn't asked to save them
+pub(crate) fn save_held<T>(fns: Option<HeldFns<T>>, held: &[T]) -> NodeState {
+    match fns {
+        Some((save, _)) => save(held),
+        None => NodeState::Empty,
+    }
+}
+
+/// The examples that were saved, or none if the node wasn't asked to save them
+pub(crate) fn load_held<T>(fns: Option<HeldFns<T>>, saved: &NodeState) -> Vec<T> {
+    match fns {
+        Some((_, load)) => load(saved),
+        None => vec![],
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::marker::PhantomData;
 
-use crate::pipeline::Node;
+use crate::pipeline::{Node, NodeDescription, Profile, SizeHint, TryNode};
 
 pub struct Map<I, N: Node<I>> {
     _phantom: PhantomData<I>,
@@ -21,7 +21,7 @@ impl<I, N: Node<I> + Clone> Clone for Map<I, N> {
 impl<I, O, E: Node<I, Output = O>> Map<I, E> {
     pub fn new(node: E) -> Self {
         Map {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             node,
         }
     }
@@ -33,6 +33,78 @@ impl<I, O, N: Node<I, Output = O>> Node<Vec<I>> for Map<I, N> {
     fn process(&mut self, input: Vec<I>) -> Self::Output {
         input.into_iter().map(|i| self.node.process(i)).collect()
     }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Map").child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+/// Map a fallible node over every sample. Run as a Node, each sample comes out as a Result.
+/// Run as a TryNode, the first failed sample fails the whole batch.
+pub struct TryMap<I, N: TryNode<I>> {
+    _phantom: PhantomData<I>,
+    node: N,
+}
+
+impl<I, N: TryNode<I> + Clone> Clone for TryMap<I, N> {
+    fn clone(&self) -> Self {
+        Self {
+            _phantom: self._phantom,
+            node: self.node.clone(),
+        }
+    }
+}
+
+impl<I, O, E: TryNode<I, Output = O>> TryMap<I, E> {
+    pub fn new(node: E) -> Self {
+        TryMap {
+            _phantom: PhantomData,
+            node,
+        }
+    }
+}
+
+impl<I, O, N: TryNode<I, Output = O>> Node<Vec<I>> for TryMap<I, N> {
+    type Output = Vec<Result<O, N::Error>>;
+
+    fn process(&mut self, input: Vec<I>) -> Self::Output {
+        input
+            .into_iter()
+            .map(|i| self.node.try_process(i))
+            .collect()
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("TryMap").child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+impl<I, O, N: TryNode<I, Output = O>> TryNode<Vec<I>> for TryMap<I, N> {
+    type Output = Vec<O>;
+    type Error = N::Error;
+
+    fn try_process(&mut self, input: Vec<I>) -> Result<Self::Output, Self::Error> {
+        input
+            .into_iter()
+            .map(|i| self.node.try_process(i))
+            .collect()
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("TryMap").child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
 }
 
 pub trait ExtendNodeMap<Input, Output, E: Node<Input, Output = Vec<Output>>> {
@@ -42,6 +114,7 @@ pub trait ExtendNodeMap<Input, Output, E: Node<Input, Output = Vec<Output>>> {
         node: N,
     ) -> (E, FilterMap<Output, N>);
     fn filter<F: FnMut(&Output) -> bool>(self, function: F) -> (E, Filter<Output, F>);
+    fn try_map<O, N: TryNode<Output, Output = O>>(self, node: N) -> (E, TryMap<Output, N>);
 }
 
 impl<Input, Output, E: Node<Input, Output = Vec<Output>>> ExtendNodeMap<Input, Output, E> for E {
@@ -65,6 +138,10 @@ impl<Input, Output, E: Node<Input, Output = Vec<Output>>> ExtendNodeMap<Input, O
     fn filter<F: FnMut(&Output) -> bool>(self, function: F) -> (E, Filter<Output, F>) {
         (self, Filter::new(function))
     }
+
+    fn try_map<O, N: TryNode<Output, Output = O>>(self, node: N) -> (E, TryMap<Output, N>) {
+        (self, TryMap::new(node))
+    }
 }
 
 pub trait ExtendNodeFlatten<I, O, N: Node<Vec<I>, Output = Vec<O>>> {
@@ -85,6 +162,10 @@ impl<I: IntoIterator> Node<Vec<I>> for Flatten {
     fn process(&mut self, input: Vec<I>) -> Self::Output {
         input.into_iter().flatten().collect()
     }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.any()
+    }
 }
 
 pub struct FilterMap<I, N: Node<I>> {
@@ -95,7 +176,7 @@ pub struct FilterMap<I, N: Node<I>> {
 impl<I, O, E: Node<I, Output = Option<O>>> FilterMap<I, E> {
     pub fn new(node: E) -> Self {
         FilterMap {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             node,
         }
     }
@@ -110,6 +191,18 @@ impl<I, O, N: Node<I, Output = Option<O>>> Node<Vec<I>> for FilterMap<I, N> {
             .filter_map(|i| self.node.process(i))
             .collect()
     }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most()
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("FilterMap").child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
 }
 
 pub struct Filter<I, F: FnMut(&I) -> bool> {
@@ -120,7 +213,7 @@ pub struct Filter<I, F: FnMut(&I) -> bool> {
 impl<I, F: FnMut(&I) -> bool> Filter<I, F> {
     pub fn new(function: F) -> Self {
         Filter {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             function,
         }
     }
@@ -132,4 +225,8 @@ impl<I, F: FnMut(&I) -> bool> Node<Vec<I>> for Filter<I, F> {
     fn process(&mut self, input: Vec<I>) -> Self::Output {
         input.into_iter().filter(|i| (self.function)(i)).collect()
     }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most()
+    }
 }
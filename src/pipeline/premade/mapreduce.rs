@@ -1,6 +1,17 @@
-use std::{collections::BTreeMap, marker::PhantomData};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::pipeline::Node;
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::spill::{default_spill_dir, merge_runs, SpillDir, SpillFile, MAX_FAN_IN};
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
 
 /// Implements the MapReduce operation as seen here: https://research.google/pubs/pub62/
 pub struct MapReduce<I, K, V, O, Map: Fn(I) -> Vec<(K, V)>, Reduce: Fn((K, Vec<V>)) -> Vec<O>> {
@@ -28,7 +39,34 @@ impl<I, K: Ord, V, O, Map: Fn(I) -> Vec<(K, V)>, Reduce: Fn((K, Vec<V>)) -> Vec<
         Self {
             map,
             reduce,
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Group keys across everything a source node produces instead of within each call
+    pub fn streaming<N: Node<Vec<()>, Output = Vec<I>>>(
+        self,
+        node: N,
+    ) -> StreamingMapReduce<N, I, K, V, O, Map, Reduce> {
+        let cores = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        StreamingMapReduce {
+            node,
+            map: self.map,
+            reduce: self.reduce,
+            combiner: None,
+            max_in_memory: 1_000_000,
+            spill_dir: default_spill_dir(),
+            partitions: cores * 4,
+            reducers: cores,
+            consumed: false,
+            pending: VecDeque::new(),
+            spills: None,
+            ready: VecDeque::new(),
+            delivered: 0,
+            skip: 0,
+            _phantom: PhantomData,
         }
     }
 }
@@ -44,6 +82,10 @@ impl<I, K: Ord, V, O, Map: Fn(I) -> Vec<(K, V)>, Reduce: Fn((K, Vec<V>)) -> Vec<
             .flat_map(&self.reduce)
             .collect()
     }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.any()
+    }
 }
 
 fn group<A, B, I>(v: I) -> BTreeMap<A, Vec<B>>
@@ -57,3 +99,336 @@ where
     }
     result
 }
+
+/// The groups of one hash partition, held in memory and spilled to disk as runs sorted by key
+struct Partition<K, V> {
+    groups: HashMap<K, Vec<V>>,
+    runs: Vec<SpillFile<(K, Vec<V>)>>,
+}
+
+impl<K, V> Default for Partition<K, V> {
+    fn default() -> Self {
+        Partition {
+            groups: HashMap::new(),
+            runs: vec![],
+        }
+    }
+}
+
+impl<K: Ord + Serialize, V: Serialize> Partition<K, V> {
+    /// Write the groups held in memory to a new run
+    fn spill(&mut self, dir: &SpillDir) {
+        if !self.groups.is_empty() {
+            let mut groups = self.groups.drain().collect::<Vec<_>>();
+            groups.sort_unstable_by(compare_keys);
+            self.runs.push(dir.write(&groups));
+        }
+    }
+}
+
+fn compare_keys<K: Ord, V>(a: &(K, Vec<V>), b: &(K, Vec<V>)) -> Ordering {
+    a.0.cmp(&b.0)
+}
+
+/// Add values to a group, folding them with the combiner if there is one
+fn insert<V>(
+    group: &mut Vec<V>,
+    values: Vec<V>,
+    combiner: Option<&(dyn Fn(V, V) -> V + Send + Sync)>,
+) {
+    match combiner {
+        Some(combiner) => {
+            for value in values {
+                let combined = match group.pop() {
+                    Some(current) => combiner(current, value),
+                    None => value,
+                };
+                group.push(combined);
+            }
+        }
+        None => group.extend(values),
+    }
+}
+
+/// Reduce a partition's groups in key order, merging its runs if it was spilled. Runs hold each key at most once, so equal keys from different runs are combined as they're merged
+fn reduce_partition<K, V, O>(
+    partition: Partition<K, V>,
+    spills: Option<&SpillDir>,
+    combiner: Option<&(dyn Fn(V, V) -> V + Send + Sync)>,
+    reduce: &(dyn Fn((K, Vec<V>)) -> Vec<O> + Sync),
+) -> Vec<O>
+where
+    K: Ord + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    let groups: Box<dyn Iterator<Item = (K, Vec<V>)>> = match spills {
+        Some(dir) => Box::new(merge_runs(
+            dir,
+            partition.runs,
+            Arc::new(compare_keys::<K, V>),
+            MAX_FAN_IN,
+        )),
+        None => {
+            let mut groups = partition.groups.into_iter().collect::<Vec<_>>();
+            groups.sort_unstable_by(compare_keys);
+            Box::new(groups.into_iter())
+        }
+    };
+    let mut output = vec![];
+    let mut current: Option<(K, Vec<V>)> = None;
+    for (key, values) in groups {
+        match &mut current {
+            Some((current_key, group)) if *current_key == key => insert(group, values, combiner),
+            _ => {
+                if let Some(group) = current.replace((key, values)) {
+                    output.extend(reduce(group));
+                }
+            }
+        }
+    }
+    if let Some(group) = current {
+        output.extend(reduce(group));
+    }
+    output
+}
+
+/// A MapReduce over everything a source node produces. The whole source is mapped on the first call, hashing pairs into partitions that are spilled to disk as key sorted runs once `max_in_memory` values are held.
+/// Partitions are then reduced `reducers` at a time in parallel, merging their runs, and the output streams out in chunks the size of each input, sorted by key within each partition.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// // Word counts over a whole corpus
+/// let lines = vec!["a b".to_string(), "b".to_string()];
+/// let mut counter = MapReduce::new(
+///     |line: String| line.split(' ').map(|w| (w.to_string(), 1)).collect(),
+///     |(word, counts): (String, Vec<usize>)| vec![(word, counts.into_iter().sum::<usize>())],
+/// )
+/// .streaming(VecLoader::new(lines))
+/// .combiner(|a, b| a + b);
+/// counter.reset(Epoch::default());
+/// let mut counts = counter.process(vec![(); 10]);
+/// counts.sort();
+/// assert_eq!(counts, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+/// ```
+pub struct StreamingMapReduce<N, I, K, V, O, Map, Reduce> {
+    node: N,
+    map: Map,
+    reduce: Reduce,
+    combiner: Option<Box<dyn Fn(V, V) -> V + Send + Sync>>,
+    max_in_memory: usize,
+    spill_dir: PathBuf,
+    partitions: usize,
+    reducers: usize,
+    consumed: bool,                     // Whether the source was mapped this epoch
+    pending: VecDeque<Partition<K, V>>, // Partitions left to reduce
+    spills: Option<SpillDir>,
+    ready: VecDeque<O>, // Reduced output that hasn't been delivered
+    delivered: usize,
+    skip: usize, // Outputs delivered before a restore, dropped once reduced
+    _phantom: PhantomData<fn(I)>,
+}
+
+impl<N, I, K, V, O, Map, Reduce> StreamingMapReduce<N, I, K, V, O, Map, Reduce> {
+    /// Fold values for the same key together as they're mapped, so fewer are held and spilled. It should agree with the reducer, like summing counts
+    pub fn combiner(self, combiner: impl Fn(V, V) -> V + Send + Sync + 'static) -> Self {
+        StreamingMapReduce {
+            combiner: Some(Box::new(combiner)),
+            ..self
+        }
+    }
+
+    /// The most values held in memory before the partitions are spilled to disk
+    pub fn max_in_memory(self, max_in_memory: usize) -> Self {
+        StreamingMapReduce {
+            max_in_memory: max_in_memory.max(1),
+            ..self
+        }
+    }
+
+    /// Directory to spill partitions into, defaulting to the system temp directory
+    pub fn spill_dir<P: AsRef<Path>>(self, spill_dir: P) -> Self {
+        StreamingMapReduce {
+            spill_dir: spill_dir.as_ref().to_path_buf(),
+            ..self
+        }
+    }
+
+    /// Number of hash partitions. More partitions make each one smaller to reduce. Defaults to four per available core
+    pub fn partitions(self, partitions: usize) -> Self {
+        StreamingMapReduce {
+            partitions: partitions.max(1),
+            ..self
+        }
+    }
+
+    /// How many partitions are reduced at once, which bounds the reduced output held in memory. Defaults to one per available core
+    pub fn reducers(self, reducers: usize) -> Self {
+        StreamingMapReduce {
+            reducers: reducers.max(1),
+            ..self
+        }
+    }
+}
+
+impl<N, I, K, V, O, Map, Reduce> StreamingMapReduce<N, I, K, V, O, Map, Reduce>
+where
+    N: Node<Vec<()>, Output = Vec<I>>,
+    K: Hash + Eq + Ord + Serialize + DeserializeOwned + Send,
+    V: Serialize + DeserializeOwned + Send,
+    O: Send,
+    Map: Fn(I) -> Vec<(K, V)>,
+    Reduce: Fn((K, Vec<V>)) -> Vec<O> + Sync,
+{
+    /// Map the whole source into partitions
+    fn consume(&mut self, chunk_size: usize) {
+        let mut partitions = (0..self.partitions)
+            .map(|_| Partition::default())
+            .collect::<Vec<_>>();
+        let mut held = 0; // Values held in memory across the partitions
+        while !self.node.size_hint(SizeHint::exact(0)).is_empty() {
+            for (key, value) in self
+                .node
+                .process(vec![(); chunk_size])
+                .into_iter()
+                .flat_map(&self.map)
+            {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                let partition =
+                    &mut partitions[(hasher.finish() % self.partitions as u64) as usize];
+                let group = partition.groups.entry(key).or_default();
+                let len = group.len();
+                insert(group, vec![value], self.combiner.as_deref());
+                held = held + group.len() - len;
+            }
+            if held >= self.max_in_memory {
+                let spills = self
+                    .spills
+                    .get_or_insert_with(|| SpillDir::new(&self.spill_dir));
+                for partition in &mut partitions {
+                    partition.spill(spills);
+                }
+                held = 0;
+            }
+        }
+        // Once anything is spilled, every partition is merged from runs
+        if let Some(spills) = &self.spills {
+            for partition in &mut partitions {
+                partition.spill(spills);
+            }
+        }
+        self.pending = partitions.into();
+        self.consumed = true;
+    }
+
+    /// Reduce the next partitions in parallel
+    fn reduce_next(&mut self) {
+        let partitions = self
+            .pending
+            .drain(..self.reducers.min(self.pending.len()))
+            .collect::<Vec<_>>();
+        let (spills, combiner, reduce) =
+            (self.spills.as_ref(), self.combiner.as_deref(), &self.reduce);
+        let outputs = partitions
+            .into_par_iter()
+            .map(|partition| reduce_partition(partition, spills, combiner, reduce))
+            .collect::<Vec<_>>();
+        self.ready.extend(outputs.into_iter().flatten());
+        if self.pending.is_empty() {
+            self.spills = None;
+        }
+    }
+}
+
+impl<N, I, K, V, O, Map, Reduce> Node<Vec<()>> for StreamingMapReduce<N, I, K, V, O, Map, Reduce>
+where
+    N: Node<Vec<()>, Output = Vec<I>>,
+    K: Hash + Eq + Ord + Serialize + DeserializeOwned + Send,
+    V: Serialize + DeserializeOwned + Send,
+    O: Send,
+    Map: Fn(I) -> Vec<(K, V)>,
+    Reduce: Fn((K, Vec<V>)) -> Vec<O> + Sync,
+{
+    type Output = Vec<O>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        if !self.consumed {
+            self.consume(input.len().max(1));
+        }
+        while self.ready.len() < input.len() + self.skip && !self.pending.is_empty() {
+            self.reduce_next();
+        }
+        let skipped = self.skip.min(self.ready.len());
+        self.ready.drain(..skipped);
+        self.skip -= skipped;
+        let output = self
+            .ready
+            .drain(..input.len().min(self.ready.len()))
+            .collect::<Vec<_>>();
+        self.delivered += output.len();
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+        self.consumed = false;
+        self.pending.clear();
+        self.spills = None;
+        self.ready.clear();
+        self.delivered = 0;
+        self.skip = 0;
+    }
+
+    /// Estimated as one output per source example until the source is mapped, then as the outputs reduced so far.
+    /// A partition's output count isn't known until it's reduced, so the estimate only grows as partitions are
+    fn data_remaining(&self, before: usize) -> usize {
+        match self.consumed {
+            true => self.size_hint(SizeHint::exact(before)).lower,
+            false => self.node.data_remaining(before).saturating_sub(self.skip),
+        }
+    }
+
+    /// Nothing is known about the output count until every partition is reduced
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        if !self.consumed && self.node.size_hint(before).is_empty() {
+            return SizeHint::exact(0);
+        }
+        let ready = self.ready.len().saturating_sub(self.skip);
+        match self.consumed && self.pending.is_empty() {
+            true => SizeHint::exact(ready),
+            false => SizeHint {
+                lower: ready,
+                upper: None,
+            },
+        }
+    }
+
+    /// How many outputs were delivered. The source is mapped and reduced again on load, and those are skipped
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.delivered)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.consumed = false;
+        self.pending.clear();
+        self.spills = None;
+        self.ready.clear();
+        self.delivered = state.index();
+        self.skip = self.delivered;
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("StreamingMapReduce")
+            .param("partitions", self.partitions)
+            .param("reducers", self.reducers)
+            .param("max_in_memory", self.max_in_memory)
+            .param("combiner", self.combiner.is_some())
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
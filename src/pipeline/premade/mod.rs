@@ -3,12 +3,30 @@ pub use stateful::*;
 mod batch;
 pub use batch::*;
 mod sort;
+mod spill;
 pub use sort::*;
 mod mapreduce;
 pub use mapreduce::*;
 mod map;
 pub use map::*;
+mod par_map;
+pub use par_map::*;
+mod held;
 mod shuffle;
 pub use shuffle::*;
 mod selector;
 pub use selector::*;
+mod profile;
+pub use profile::*;
+mod cache;
+pub use cache::*;
+mod prefetch;
+pub use prefetch::*;
+mod window;
+pub use window::*;
+mod bucket;
+pub use bucket::*;
+mod dedup;
+pub use dedup::*;
+mod stream;
+pub use stream::*;
@@ -0,0 +1,198 @@
+use std::marker::PhantomData;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::pipeline::{Node, NodeDescription, Profile, SizeHint};
+
+/// Map a node over each sample in parallel. Each worker thread gets its own clone of the node, and output order matches input order.
+pub struct ParMap<I, N: Node<I>> {
+    _phantom: PhantomData<I>,
+    workers: Vec<N>,
+    pool: Option<ThreadPool>, // Built on the first call
+}
+
+impl<I, N: Node<I> + Clone> Clone for ParMap<I, N> {
+    fn clone(&self) -> Self {
+        Self {
+            _phantom: self._phantom,
+            workers: self.workers.clone(),
+            pool: None,
+        }
+    }
+}
+
+impl<I, O, N: Node<I, Output = O> + Clone> ParMap<I, N> {
+    /// Create a ParMap with one worker per available core
+    pub fn new(node: N) -> Self {
+        Self::with_workers(node, default_threads())
+    }
+
+    /// Set the number of worker threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.workers.resize(threads.max(1), self.workers[0].clone());
+        self
+    }
+
+    fn with_workers(node: N, threads: usize) -> Self {
+        let threads = threads.max(1);
+        ParMap {
+            _phantom: PhantomData,
+            workers: vec![node; threads],
+            pool: None,
+        }
+    }
+}
+
+impl<I: Send, O: Send, N: Node<I, Output = O> + Send> Node<Vec<I>> for ParMap<I, N> {
+    type Output = Vec<O>;
+
+    fn process(&mut self, input: Vec<I>) -> Self::Output {
+        let pool = self
+            .pool
+            .get_or_insert_with(|| build_pool(self.workers.len()));
+        par_process(pool, &mut self.workers, input, |node, i| {
+            Some(node.process(i))
+        })
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("ParMap")
+            .param("threads", self.workers.len())
+            .child(self.workers[0].describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        // Worker clones share their profiles
+        self.workers[0].profiles()
+    }
+}
+
+/// Filter map a node over each sample in parallel. Each worker thread gets its own clone of the node, and output order matches input order.
+pub struct ParFilterMap<I, N: Node<I>> {
+    _phantom: PhantomData<I>,
+    workers: Vec<N>,
+    pool: Option<ThreadPool>, // Built on the first call
+}
+
+impl<I, N: Node<I> + Clone> Clone for ParFilterMap<I, N> {
+    fn clone(&self) -> Self {
+        Self {
+            _phantom: self._phantom,
+            workers: self.workers.clone(),
+            pool: None,
+        }
+    }
+}
+
+impl<I, O, N: Node<I, Output = Option<O>> + Clone> ParFilterMap<I, N> {
+    /// Create a ParFilterMap with one worker per available core
+    pub fn new(node: N) -> Self {
+        Self::with_workers(node, default_threads())
+    }
+
+    /// Set the number of worker threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.workers.resize(threads.max(1), self.workers[0].clone());
+        self
+    }
+
+    fn with_workers(node: N, threads: usize) -> Self {
+        let threads = threads.max(1);
+        ParFilterMap {
+            _phantom: PhantomData,
+            workers: vec![node; threads],
+            pool: None,
+        }
+    }
+}
+
+impl<I: Send, O: Send, N: Node<I, Output = Option<O>> + Send> Node<Vec<I>> for ParFilterMap<I, N> {
+    type Output = Vec<O>;
+
+    fn process(&mut self, input: Vec<I>) -> Self::Output {
+        let pool = self
+            .pool
+            .get_or_insert_with(|| build_pool(self.workers.len()));
+        par_process(pool, &mut self.workers, input, |node, i| node.process(i))
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.at_most()
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("ParFilterMap")
+            .param("threads", self.workers.len())
+            .child(self.workers[0].describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        // Worker clones share their profiles
+        self.workers[0].profiles()
+    }
+}
+
+pub trait ExtendNodeParMap<Input, Output, E: Node<Input, Output = Vec<Output>>> {
+    fn par_map<O, N: Node<Output, Output = O> + Clone>(self, node: N) -> (E, ParMap<Output, N>);
+    fn par_filter_map<O, N: Node<Output, Output = Option<O>> + Clone>(
+        self,
+        node: N,
+    ) -> (E, ParFilterMap<Output, N>);
+}
+
+impl<Input, Output, E: Node<Input, Output = Vec<Output>>> ExtendNodeParMap<Input, Output, E> for E {
+    fn par_map<O, N: Node<Output, Output = O> + Clone>(self, node: N) -> (E, ParMap<Output, N>) {
+        (self, ParMap::new(node))
+    }
+
+    fn par_filter_map<O, N: Node<Output, Output = Option<O>> + Clone>(
+        self,
+        node: N,
+    ) -> (E, ParFilterMap<Output, N>) {
+        (self, ParFilterMap::new(node))
+    }
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+fn build_pool(threads: usize) -> ThreadPool {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Failed to build thread pool!")
+}
+
+/// Split the input into one contiguous chunk per worker, run them on the pool and stitch the outputs back together in order
+fn par_process<I: Send, O: Send, N: Send, F: Fn(&mut N, I) -> Option<O> + Sync>(
+    pool: &ThreadPool,
+    workers: &mut [N],
+    mut input: Vec<I>,
+    function: F,
+) -> Vec<O> {
+    let chunk_size = input.len().div_ceil(workers.len()).max(1);
+    let mut chunks = Vec::with_capacity(workers.len());
+    while !input.is_empty() {
+        chunks.push(
+            input
+                .drain(..usize::min(chunk_size, input.len()))
+                .collect::<Vec<_>>(),
+        );
+    }
+    let mut outputs: Vec<Vec<O>> = (0..chunks.len()).map(|_| vec![]).collect();
+    let function = &function;
+    pool.scope(|s| {
+        for ((worker, chunk), output) in workers.iter_mut().zip(chunks).zip(outputs.iter_mut()) {
+            s.spawn(move |_| {
+                *output = chunk
+                    .into_iter()
+                    .filter_map(|i| function(worker, i))
+                    .collect();
+            });
+        }
+    });
+    outputs.into_iter().flatten().collect()
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::VecDeque,
+    panic,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+
+/// A chunk of output loaded ahead of time
+struct Chunk<T> {
+    items: VecDeque<T>,
+    before: NodeState, // Node state before the chunk was loaded
+    after: NodeState,
+    delivered: usize,
+}
+
+/// How much the node has left, published by whoever last ran it so queries don't wait on a chunk being loaded
+#[derive(Clone, Copy)]
+struct Sizes {
+    remaining: usize,
+    hint: SizeHint,
+    queued: usize, // Items sent by the thread that weren't received yet
+}
+
+impl Sizes {
+    /// Sizes of a node with nothing queued. The node is a source, so it's asked with nothing before it
+    fn of<N: Node<Vec<()>>>(node: &N) -> Self {
+        Sizes {
+            remaining: node.data_remaining(0),
+            hint: node.size_hint(SizeHint::exact(0)),
+            queued: 0,
+        }
+    }
+}
+
+/// Runs a source node on a background thread, keeping up to `depth` chunks of its output queued ahead of demand.
+/// The node is called with chunks the size of the first input, and its output is regrouped to the size of each input.
+/// Size queries read what the thread published after its last chunk, so they never wait on a chunk being loaded.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// // Files load in the background while the downstream stages work
+/// let pipeline = FileLoader::new(vec!["file1".into()])
+///     .prefetch(4)
+///     .map(|(_, bytes): (std::path::PathBuf, Vec<u8>)| bytes.len());
+/// ```
+pub struct Prefetch<N, T> {
+    node: Arc<Mutex<N>>,
+    depth: usize,
+    chunk_size: Option<usize>, // Taken from the first input
+    receiver: Option<Receiver<Chunk<T>>>,
+    handle: Option<JoinHandle<()>>,
+    sizes: Arc<Mutex<Sizes>>,
+    description: NodeDescription,
+    profiles: Vec<Profile>,
+    chunks: VecDeque<Chunk<T>>,
+    last_state: NodeState, // State after the last chunk that was fully delivered
+    skip: usize,           // Items delivered before a restore, dropped as they arrive
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>> + Send + 'static, T: Send + 'static> Prefetch<N, T> {
+    pub fn new(node: N, depth: usize) -> Self {
+        let last_state = node.state_dict();
+        Prefetch {
+            sizes: Arc::new(Mutex::new(Sizes::of(&node))),
+            description: node.describe(),
+            profiles: node.profiles(),
+            node: Arc::new(Mutex::new(node)),
+            depth: depth.max(1),
+            chunk_size: None,
+            receiver: None,
+            handle: None,
+            chunks: VecDeque::new(),
+            last_state,
+            skip: 0,
+        }
+    }
+
+    fn start(&mut self, chunk_size: usize) {
+        let (sender, receiver) = mpsc::sync_channel(self.depth);
+        let (node, sizes) = (self.node.clone(), self.sizes.clone());
+        self.receiver = Some(receiver);
+        self.handle = Some(thread::spawn(move || {
+            let mut state = node.lock().unwrap().state_dict();
+            loop {
+                let chunk = {
+                    let mut node = node.lock().unwrap();
+                    if node.size_hint(SizeHint::exact(0)).is_empty() {
+                        return;
+                    }
+                    let items = node.process(vec![(); chunk_size]);
+                    // Publish the chunk and what's left together, so the chunk is never missed or counted twice
+                    let published = Sizes::of(&*node);
+                    let mut sizes = sizes.lock().unwrap();
+                    *sizes = Sizes {
+                        queued: sizes.queued + items.len(),
+                        ..published
+                    };
+                    drop(sizes);
+                    let after = node.state_dict();
+                    Chunk {
+                        items: items.into(),
+                        before: std::mem::replace(&mut state, after.clone()),
+                        after,
+                        delivered: 0,
+                    }
+                };
+                if sender.send(chunk).is_err() {
+                    return; // Prefetch was reset or dropped
+                }
+            }
+        }));
+    }
+
+    /// Publish the sizes of the node while the thread isn't running
+    fn publish(&self) {
+        *self.sizes.lock().unwrap() = Sizes::of(&*self.node.lock().unwrap());
+    }
+
+    /// Wait for the thread to finish, passing on its panic
+    fn join(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            if let Err(payload) = handle.join() {
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+}
+
+impl<N, T> Prefetch<N, T> {
+    /// Stop the thread and throw away everything it loaded
+    fn stop(&mut self) {
+        self.receiver.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+        self.sizes.lock().unwrap().queued = 0;
+        self.chunks.clear();
+    }
+
+    /// The published sizes, and the items loaded but not delivered yet
+    fn sizes(&self) -> (Sizes, usize) {
+        let sizes = *self.sizes.lock().unwrap();
+        let local = self.chunks.iter().map(|c| c.items.len()).sum::<usize>();
+        (sizes, sizes.queued + local)
+    }
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>> + Send + 'static, T: Send + 'static> Node<Vec<()>>
+    for Prefetch<N, T>
+{
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        let wanted = input.len();
+        if self.receiver.is_none() {
+            let chunk_size = *self.chunk_size.get_or_insert(wanted.max(1));
+            self.start(chunk_size);
+        }
+        let mut output = Vec::with_capacity(wanted);
+        while output.len() < wanted {
+            if let Some(front) = self.chunks.front_mut() {
+                let take = (wanted - output.len()).min(front.items.len());
+                output.extend(front.items.drain(..take));
+                front.delivered += take;
+                if front.items.is_empty() {
+                    self.last_state = self.chunks.pop_front().unwrap().after;
+                }
+                continue;
+            }
+            match self.receiver.as_ref().unwrap().recv() {
+                Ok(mut chunk) => {
+                    self.sizes.lock().unwrap().queued -= chunk.items.len();
+                    let skip = self.skip.min(chunk.items.len());
+                    chunk.items.drain(..skip);
+                    chunk.delivered = skip;
+                    self.skip -= skip;
+                    self.chunks.push_back(chunk);
+                }
+                // The node ran out
+                Err(_) => {
+                    self.join();
+                    break;
+                }
+            }
+        }
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.stop();
+        let mut node = self.node.lock().unwrap();
+        node.reset(epoch);
+        self.last_state = node.state_dict();
+        drop(node);
+        self.publish();
+        self.skip = 0;
+    }
+
+    fn data_remaining(&self, _before: usize) -> usize {
+        let (sizes, buffered) = self.sizes();
+        (sizes.remaining + buffered).saturating_sub(self.skip)
+    }
+
+    fn size_hint(&self, _before: SizeHint) -> SizeHint {
+        let (sizes, buffered) = self.sizes();
+        let hint = sizes.hint + SizeHint::exact(buffered);
+        SizeHint {
+            lower: hint.lower.saturating_sub(self.skip),
+            upper: hint.upper.map(|u| u.saturating_sub(self.skip)),
+        }
+    }
+
+    /// The state before the first chunk that wasn't fully delivered, and how much of it was
+    fn state_dict(&self) -> NodeState {
+        let (state, delivered) = match self.chunks.front() {
+            Some(front) => (front.before.clone(), front.delivered),
+            None => (self.last_state.clone(), 0),
+        };
+        NodeState::List(vec![
+            state,
+            NodeState::Index(delivered),
+            NodeState::Index(self.chunk_size.unwrap_or(0)),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.stop();
+        let state = state.list();
+        self.node.lock().unwrap().load_state_dict(&state[0]);
+        self.publish();
+        self.last_state = state[0].clone();
+        self.skip = state[1].index();
+        // Chunks have to line up with the ones that were checkpointed
+        self.chunk_size = Some(state[2].index()).filter(|c| *c > 0);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Prefetch")
+            .param("depth", self.depth)
+            .child(self.description.clone())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.profiles.clone()
+    }
+}
+
+impl<N, T> Drop for Prefetch<N, T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+pub trait ExtendNodePrefetch<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Run this node on a background thread, keeping `depth` chunks of output ready
+    fn prefetch(self, depth: usize) -> Prefetch<E, T>;
+}
+
+impl<T: Send + 'static, E: Node<Vec<()>, Output = Vec<T>> + Send + 'static> ExtendNodePrefetch<T, E>
+    for E
+{
+    fn prefetch(self, depth: usize) -> Prefetch<E, T> {
+        Prefetch::new(self, depth)
+    }
+}
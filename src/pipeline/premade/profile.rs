@@ -0,0 +1,261 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, SizeHint, TryNode};
+
+/// Counters recorded by a profiled stage
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageStats {
+    pub name: String,
+    pub calls: usize,
+    pub items_in: usize,
+    pub items_out: usize,
+    /// Wall time spent inside the stage
+    pub time: Duration,
+    /// Allocations made by the stage on the calling thread. Only counted when `CountingAllocator` is the global allocator
+    pub allocations: usize,
+    pub allocated_bytes: usize,
+}
+
+/// A handle to the stats of a profiled stage, which can be read while the pipeline runs
+#[derive(Clone, Debug)]
+pub struct Profile(Arc<Mutex<StageStats>>);
+
+impl Profile {
+    /// A snapshot of the stage's stats
+    pub fn stats(&self) -> StageStats {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// The number of items in a node's input or output, used to count items through profiled stages
+pub trait ItemCount {
+    fn item_count(&self) -> usize;
+}
+
+impl<T> ItemCount for Vec<T> {
+    fn item_count(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> ItemCount for Option<T> {
+    fn item_count(&self) -> usize {
+        self.is_some() as usize
+    }
+}
+
+impl<T: ItemCount> ItemCount for &T {
+    fn item_count(&self) -> usize {
+        (*self).item_count()
+    }
+}
+
+impl<A: ItemCount, B: ItemCount> ItemCount for (A, B) {
+    fn item_count(&self) -> usize {
+        self.0.item_count().max(self.1.item_count())
+    }
+}
+
+/// Records call counts, items in and out, wall time and allocations of the node it wraps
+#[derive(Clone)]
+pub struct Profiled<N> {
+    pub node: N,
+    profile: Profile,
+}
+
+impl<N> Profiled<N> {
+    pub fn new(node: N, name: &str) -> Self {
+        Profiled {
+            node,
+            profile: Profile(Arc::new(Mutex::new(StageStats {
+                name: name.to_string(),
+                ..Default::default()
+            }))),
+        }
+    }
+
+    /// A handle to this stage's stats
+    pub fn profile(&self) -> Profile {
+        self.profile.clone()
+    }
+
+    fn record<O: ItemCount>(
+        &self,
+        items_in: usize,
+        output: &O,
+        start: Instant,
+        before: (usize, usize),
+    ) {
+        let time = start.elapsed();
+        let after = thread_allocations();
+        let mut stats = self.profile.0.lock().unwrap();
+        stats.calls += 1;
+        stats.items_in += items_in;
+        stats.items_out += output.item_count();
+        stats.time += time;
+        stats.allocations += after.0 - before.0;
+        stats.allocated_bytes += after.1 - before.1;
+    }
+
+    fn describe_inner(&self, inner: NodeDescription) -> NodeDescription {
+        NodeDescription::new("Profiled")
+            .param("name", &self.profile.0.lock().unwrap().name)
+            .child(inner)
+    }
+}
+
+impl<I: ItemCount, N: Node<I>> Node<I> for Profiled<N>
+where
+    N::Output: ItemCount,
+{
+    type Output = N::Output;
+
+    fn process(&mut self, input: I) -> Self::Output {
+        let items_in = input.item_count();
+        let (before, start) = (thread_allocations(), Instant::now());
+        let output = self.node.process(input);
+        self.record(items_in, &output, start, before);
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        self.describe_inner(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        let mut profiles = vec![self.profile()];
+        profiles.extend(self.node.profiles());
+        profiles
+    }
+}
+
+/// Only successful calls count items out, failed calls still count time and allocations
+impl<I: ItemCount, N: TryNode<I>> TryNode<I> for Profiled<N>
+where
+    N::Output: ItemCount,
+{
+    type Output = N::Output;
+    type Error = N::Error;
+
+    fn try_process(&mut self, input: I) -> Result<Self::Output, Self::Error> {
+        let items_in = input.item_count();
+        let (before, start) = (thread_allocations(), Instant::now());
+        let output = self.node.try_process(input);
+        self.record(items_in, &output.as_ref().ok(), start, before);
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node.data_remaining(before)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before)
+    }
+
+    fn state_dict(&self) -> NodeState {
+        self.node.state_dict()
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.node.load_state_dict(state);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        self.describe_inner(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        let mut profiles = vec![self.profile()];
+        profiles.extend(self.node.profiles());
+        profiles
+    }
+}
+
+pub trait ExtendNodeProfile<Input, E: Node<Input>> {
+    /// Profile this node under a name, its stats show up in the Dataloader's metrics
+    fn profile(self, name: &str) -> Profiled<E>;
+}
+
+impl<Input, E: Node<Input>> ExtendNodeProfile<Input, E> for E {
+    fn profile(self, name: &str) -> Profiled<E> {
+        Profiled::new(self, name)
+    }
+}
+
+thread_local! {
+    static ALLOCATIONS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// Allocations and bytes allocated so far on this thread
+fn thread_allocations() -> (usize, usize) {
+    ALLOCATIONS.try_with(Cell::get).unwrap_or_default()
+}
+
+/// A global allocator that counts allocations per thread, so profiled stages can report them. Install it in your binary:
+/// ```
+/// #[global_allocator]
+/// static ALLOCATOR: dataflow::pipeline::CountingAllocator = dataflow::pipeline::CountingAllocator;
+/// # fn main() {}
+/// ```
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(bytes: usize) {
+        // The thread local may already be gone while the thread shuts down
+        let _ = ALLOCATIONS.try_with(|a| {
+            let (count, total) = a.get();
+            a.set((count + 1, total + bytes));
+        });
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
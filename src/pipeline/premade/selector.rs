@@ -1,8 +1,11 @@
 use std::marker::PhantomData;
 
 use itertools::Itertools;
+use rand::distributions::{Distribution, WeightedIndex};
 
-use crate::pipeline::Node;
+use crate::pipeline::{
+    BoxedNode, Epoch, Flow, Node, NodeDescription, NodeState, Profile, SizeHint,
+};
 
 /// Equally selects from N nodes that all take in the same input and give the same output
 ///
@@ -20,13 +23,13 @@ use crate::pipeline::Node;
 /// ```
 #[derive(Default)]
 pub struct BalancedSelector<I, O> {
-    nodes: Vec<Box<dyn Node<Vec<I>, Output = Vec<O>> + Send>>,
+    nodes: Vec<BoxedNode<Vec<I>, Vec<O>>>,
     _phantom: PhantomData<(I, O)>,
 }
 
 impl<I, O> BalancedSelector<I, O> {
     pub fn add_node<N: Node<Vec<I>, Output = Vec<O>> + 'static + Send>(mut self, node: N) -> Self {
-        self.nodes.push(Box::new(node));
+        self.nodes.push(BoxedNode::new(node));
         self
     }
 }
@@ -63,9 +66,388 @@ impl<I, O> Node<Vec<I>> for BalancedSelector<I, O> {
         self.nodes.iter().map(|n| n.data_remaining(before)).sum()
     }
 
-    fn reset(&mut self) {
-        for node in &mut self.nodes {
-            node.reset();
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.nodes.iter().map(|n| n.size_hint(before)).sum()
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            node.reset(epoch.child(i as u64));
+        }
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(self.nodes.iter().map(|n| n.state_dict()).collect())
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        for (node, state) in self.nodes.iter_mut().zip(state.list()) {
+            node.load_state_dict(state);
         }
     }
+
+    fn describe(&self) -> NodeDescription {
+        self.nodes.iter().fold(
+            NodeDescription::new("BalancedSelector").flow(Flow::Parallel),
+            |description, node| description.child(node.describe()),
+        )
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.nodes.iter().flat_map(|n| n.profiles()).collect()
+    }
+}
+
+/// How a WeightedSelector weighs its children
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mixture {
+    /// Fixed weights, one per child. They don't need to sum to 1
+    Weights(Vec<f64>),
+    /// Weights proportional to each child's size at the start of the epoch, raised to 1 / temperature.
+    /// A temperature of 1 samples in proportion to size, higher temperatures get closer to uniform
+    Temperature(f64),
+}
+
+/// What a WeightedSelector does when a child runs out of data
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Exhaustion {
+    /// End the epoch when the first child runs out
+    #[default]
+    Stop,
+    /// Reset children that run out and keep sampling, until every child has run out at least once
+    Restart,
+    /// Stop sampling children that run out, renormalizing the weights over the rest
+    Drop,
+}
+
+/// Randomly mixes samples from N nodes that all take in the same input and give the same output.
+/// Each sample is drawn from a child picked with the mixture's weights.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// // Sample three times as much from the first corpus, restarting whichever runs out
+/// let mixed = WeightedSelector::new(Mixture::Weights(vec![3., 1.]))
+///     .add_node(FileLoader::new(vec!["corpus1".into()]))
+///     .add_node(FileLoader::new(vec!["corpus2".into()]))
+///     .exhaustion(Exhaustion::Restart)
+///     .seed(42);
+/// ```
+pub struct WeightedSelector<I, O> {
+    nodes: Vec<BoxedNode<Vec<I>, Vec<O>>>,
+    mixture: Mixture,
+    exhaustion: Exhaustion,
+    seed: Option<u64>, // Overrides the epoch's seed for sampling
+    epoch: Epoch,
+    calls: usize,
+    sizes: Vec<usize>,    // Size of each child at the start of the epoch
+    restarts: Vec<usize>, // How many times each child was restarted this epoch
+    exhausted: Vec<bool>, // Children that ran out at least once this epoch
+    done: bool,
+}
+
+impl<I, O> WeightedSelector<I, O> {
+    pub fn new(mixture: Mixture) -> Self {
+        match &mixture {
+            Mixture::Weights(weights) => assert!(
+                weights.iter().all(|w| w.is_finite() && *w >= 0.),
+                "WeightedSelector weights must be finite and non-negative, got {weights:?}"
+            ),
+            Mixture::Temperature(temperature) => assert!(
+                temperature.is_finite() && *temperature > 0.,
+                "WeightedSelector temperature must be finite and positive, got {temperature}"
+            ),
+        }
+        WeightedSelector {
+            nodes: vec![],
+            mixture,
+            exhaustion: Exhaustion::default(),
+            seed: None,
+            epoch: Epoch::new(0, rand::random()),
+            calls: 0,
+            sizes: vec![],
+            restarts: vec![],
+            exhausted: vec![],
+            done: false,
+        }
+    }
+
+    pub fn add_node<N: Node<Vec<I>, Output = Vec<O>> + 'static + Send>(mut self, node: N) -> Self {
+        if let Mixture::Weights(weights) = &self.mixture {
+            assert!(
+                self.nodes.len() < weights.len(),
+                "WeightedSelector has {} weights, can't add node {}",
+                weights.len(),
+                self.nodes.len() + 1
+            );
+        }
+        self.sizes.push(node.data_remaining(usize::MAX));
+        self.restarts.push(0);
+        self.exhausted.push(false);
+        self.nodes.push(BoxedNode::new(node));
+        self
+    }
+
+    pub fn exhaustion(self, exhaustion: Exhaustion) -> Self {
+        WeightedSelector { exhaustion, ..self }
+    }
+
+    /// Seed the sampling, instead of seeding it from the epoch
+    pub fn seed(self, seed: u64) -> Self {
+        WeightedSelector {
+            seed: Some(seed),
+            ..self
+        }
+    }
+
+    /// The epoch a child is reset with, which changes every time it restarts
+    fn child_epoch(&self, index: usize) -> Epoch {
+        match self.restarts[index] {
+            0 => self.epoch.child(index as u64),
+            restarts => self.epoch.child(index as u64).child(restarts as u64),
+        }
+    }
+
+    fn weight(&self, index: usize) -> f64 {
+        match &self.mixture {
+            Mixture::Weights(weights) => weights[index],
+            Mixture::Temperature(temperature) => (self.sizes[index] as f64).powf(1. / temperature),
+        }
+    }
+
+    fn is_active(&self, index: usize) -> bool {
+        self.weight(index) > 0. && !(self.exhaustion == Exhaustion::Drop && self.exhausted[index])
+    }
+
+    /// Apply the exhaustion policy to children that ran out. Returns the remaining data of each child
+    #[allow(clippy::needless_range_loop)]
+    fn handle_exhausted(&mut self) -> Vec<usize> {
+        let mut remaining = self
+            .nodes
+            .iter()
+            .map(|n| n.data_remaining(usize::MAX))
+            .collect::<Vec<_>>();
+        for i in 0..self.nodes.len() {
+            if remaining[i] > 0 || !self.is_active(i) {
+                continue;
+            }
+            self.exhausted[i] = true;
+            match self.exhaustion {
+                Exhaustion::Stop => self.done = true,
+                Exhaustion::Drop => {}
+                Exhaustion::Restart => {
+                    if (0..self.nodes.len()).all(|i| self.exhausted[i] || self.weight(i) == 0.) {
+                        self.done = true;
+                    } else {
+                        self.restarts[i] += 1;
+                        let epoch = self.child_epoch(i);
+                        self.nodes[i].reset(epoch);
+                        remaining[i] = self.nodes[i].data_remaining(usize::MAX);
+                    }
+                }
+            }
+        }
+        if !(0..self.nodes.len()).any(|i| self.is_active(i) && remaining[i] > 0) {
+            self.done = true;
+        }
+        remaining
+    }
+}
+
+impl<I, O> Node<Vec<I>> for WeightedSelector<I, O> {
+    type Output = Vec<O>;
+
+    fn process(&mut self, mut input: Vec<I>) -> Self::Output {
+        let sampling = match self.seed {
+            Some(seed) => Epoch::new(self.epoch.index, seed),
+            None => self.epoch,
+        };
+        let mut rng = sampling.child(self.calls as u64).rng();
+        self.calls += 1;
+        let mut output = Vec::with_capacity(input.len());
+        // Sample in rounds, each ending when a child runs out or the input is used up
+        while !input.is_empty() {
+            let remaining = self.handle_exhausted();
+            if self.done {
+                break;
+            }
+            let weights = (0..self.nodes.len())
+                .map(|i| match self.is_active(i) && remaining[i] > 0 {
+                    true => self.weight(i),
+                    false => 0.,
+                })
+                .collect::<Vec<_>>();
+            let distribution = WeightedIndex::new(&weights).unwrap();
+            let mut choices = vec![];
+            let mut counts = vec![0; self.nodes.len()];
+            while choices.len() < input.len() {
+                let choice = distribution.sample(&mut rng);
+                choices.push(choice);
+                counts[choice] += 1;
+                if counts[choice] == remaining[choice] {
+                    break;
+                }
+            }
+
+            // Give each child its share of the input, then interleave the outputs in the sampled order
+            let mut inputs = (0..self.nodes.len()).map(|_| vec![]).collect::<Vec<_>>();
+            for (choice, i) in choices.iter().zip(input.drain(..choices.len())) {
+                inputs[*choice].push(i);
+            }
+            let mut outputs = self
+                .nodes
+                .iter_mut()
+                .zip(inputs)
+                .map(|(node, inputs)| match inputs.is_empty() {
+                    true => vec![].into_iter(),
+                    false => node.process(inputs).into_iter(),
+                })
+                .collect::<Vec<_>>();
+            output.extend(choices.iter().filter_map(|c| outputs[*c].next()));
+            output.extend(outputs.into_iter().flatten());
+        }
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        if let Mixture::Weights(weights) = &self.mixture {
+            assert_eq!(
+                weights.len(),
+                self.nodes.len(),
+                "WeightedSelector has {} weights but {} nodes",
+                weights.len(),
+                self.nodes.len()
+            );
+        }
+        self.epoch = epoch;
+        self.calls = 0;
+        self.done = false;
+        self.restarts = vec![0; self.nodes.len()];
+        self.exhausted = vec![false; self.nodes.len()];
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            node.reset(epoch.child(i as u64));
+        }
+        self.sizes = self
+            .nodes
+            .iter()
+            .map(|n| n.data_remaining(usize::MAX))
+            .collect();
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        if self.done {
+            return 0;
+        }
+        let remaining = self
+            .nodes
+            .iter()
+            .map(|n| n.data_remaining(before))
+            .collect::<Vec<_>>();
+        let active = (0..self.nodes.len())
+            .filter(|i| self.is_active(*i))
+            .collect::<Vec<_>>();
+        let total_weight = active.iter().map(|i| self.weight(*i)).sum::<f64>();
+        // Expected samples drawn before a child runs out
+        let until_exhausted =
+            |i: &usize| (remaining[*i] as f64 * total_weight / self.weight(*i)) as usize;
+        match self.exhaustion {
+            Exhaustion::Stop => active.iter().map(until_exhausted).min().unwrap_or(0),
+            Exhaustion::Drop => active.iter().map(|i| remaining[*i]).sum(),
+            Exhaustion::Restart => active
+                .iter()
+                .filter(|i| !self.exhausted[**i])
+                .map(until_exhausted)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        if self.done {
+            return SizeHint::exact(0);
+        }
+        let active = (0..self.nodes.len())
+            .filter(|i| self.is_active(*i))
+            .map(|i| (i, self.nodes[i].size_hint(before)))
+            .collect::<Vec<_>>();
+        match self.exhaustion {
+            // Every sample comes from an active child
+            Exhaustion::Drop => active.iter().map(|(_, hint)| *hint).sum(),
+            // Sampling stops once a child has given all it has
+            Exhaustion::Stop if active.iter().any(|(_, hint)| hint.is_empty()) => {
+                SizeHint::exact(0)
+            }
+            Exhaustion::Stop => SizeHint {
+                lower: active.iter().map(|(_, h)| h.lower).min().unwrap_or(0),
+                upper: active.iter().map(|(_, hint)| *hint).sum::<SizeHint>().upper,
+            },
+            // Children that haven't run out yet have to be drained, restarted ones could go on for anything up to a full pass
+            Exhaustion::Restart => {
+                let unfinished = active
+                    .iter()
+                    .filter(|(i, _)| !self.exhausted[*i])
+                    .map(|(_, hint)| *hint)
+                    .collect::<Vec<_>>();
+                if unfinished.iter().all(|hint| hint.is_empty()) {
+                    SizeHint::exact(0)
+                } else {
+                    SizeHint {
+                        lower: unfinished.into_iter().sum::<SizeHint>().lower,
+                        upper: None,
+                    }
+                }
+            }
+        }
+    }
+
+    fn state_dict(&self) -> NodeState {
+        let indexes = |values: &mut dyn Iterator<Item = usize>| {
+            NodeState::List(values.map(NodeState::Index).collect())
+        };
+        NodeState::List(vec![
+            NodeState::Index(self.calls),
+            NodeState::Index(self.done as usize),
+            indexes(&mut self.restarts.iter().copied()),
+            indexes(&mut self.exhausted.iter().map(|e| *e as usize)),
+            NodeState::List(self.nodes.iter().map(|n| n.state_dict()).collect()),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        self.calls = state[0].index();
+        self.done = state[1].index() == 1;
+        self.restarts = state[2].list().iter().map(|s| s.index()).collect();
+        self.exhausted = state[3].list().iter().map(|s| s.index() == 1).collect();
+        for (i, state) in state[4].list().iter().enumerate() {
+            // Restarted children were reset with a different epoch
+            if self.restarts[i] > 0 {
+                let epoch = self.child_epoch(i);
+                self.nodes[i].reset(epoch);
+            }
+            self.nodes[i].load_state_dict(state);
+        }
+    }
+
+    fn describe(&self) -> NodeDescription {
+        let description = match &self.mixture {
+            Mixture::Weights(weights) => {
+                NodeDescription::new("WeightedSelector").param("weights", format!("{weights:?}"))
+            }
+            Mixture::Temperature(temperature) => {
+                NodeDescription::new("WeightedSelector").param("temperature", temperature)
+            }
+        };
+        self.nodes.iter().fold(
+            description
+                .param("exhaustion", format!("{:?}", self.exhaustion))
+                .flow(Flow::Parallel),
+            |description, node| description.child(node.describe()),
+        )
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.nodes.iter().flat_map(|n| n.profiles()).collect()
+    }
 }
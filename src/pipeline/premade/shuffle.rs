@@ -1,17 +1,22 @@
-use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rand::{seq::SliceRandom, Rng};
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::pipeline::Node;
+use super::held::{load_held, save_held, serde_fns, HeldFns};
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
 use std::marker::PhantomData;
 
+/// Shuffles each batch. Each call's rng is derived from the epoch and the number of calls so far, so the shuffles can be replayed
 pub struct Shuffle<T> {
-    rng: StdRng,
+    epoch: Epoch,
+    calls: usize,
     _phantom: PhantomData<T>,
 }
 
 impl<T> Default for Shuffle<T> {
     fn default() -> Self {
         Self {
-            rng: StdRng::from_entropy(),
+            epoch: Epoch::new(0, rand::random()),
+            calls: 0,
             _phantom: Default::default(),
         }
     }
@@ -21,7 +26,156 @@ impl<T> Node<Vec<T>> for Shuffle<T> {
     type Output = Vec<T>;
 
     fn process(&mut self, mut input: Vec<T>) -> Self::Output {
-        input.shuffle(&mut self.rng);
+        input.shuffle(&mut self.epoch.child(self.calls as u64).rng());
+        self.calls += 1;
         input
     }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.epoch = epoch;
+        self.calls = 0;
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.calls)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.calls = state.index();
+    }
+}
+
+/// Shuffles the examples a source node produces across calls, by keeping a reservoir of them. Once the buffer is full, each new example swaps out a random buffered one.
+/// Each call pulls from the source until it has as many examples as it was given inputs, and the buffer drains in a random order once the source runs out.
+///
+/// Larger buffers mix sorted or clustered sources better, at the cost of holding more examples in memory.
+/// Buffered examples are only saved in checkpoints with `with_serde_state`, otherwise they're lost when restoring.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut shuffle = VecLoader::new((0..10).collect::<Vec<usize>>()).shuffle_buffer(3);
+/// shuffle.reset(Epoch::default());
+/// assert_eq!(shuffle.process(vec![(); 4]).len(), 4);
+/// assert_eq!(shuffle.process(vec![(); 10]).len(), 6);
+/// ```
+pub struct ShuffleBuffer<N, T> {
+    node: N,
+    capacity: usize,
+    buffer: Vec<T>,
+    epoch: Epoch,
+    calls: usize,
+    buffer_fns: Option<HeldFns<T>>,
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T> ShuffleBuffer<N, T> {
+    pub fn new(node: N, capacity: usize) -> Self {
+        assert!(capacity > 0, "ShuffleBuffer capacity must be at least 1");
+        ShuffleBuffer {
+            node,
+            capacity,
+            buffer: Vec::with_capacity(capacity),
+            epoch: Epoch::new(0, rand::random()),
+            calls: 0,
+            buffer_fns: None,
+        }
+    }
+
+    /// Save the buffered examples in checkpoints with serde, so restoring doesn't lose them
+    pub fn with_serde_state(self) -> Self
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        ShuffleBuffer {
+            buffer_fns: Some(serde_fns()),
+            ..self
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.node.size_hint(SizeHint::exact(0)).is_empty()
+    }
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T> Node<Vec<()>> for ShuffleBuffer<N, T> {
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        let mut rng = self.epoch.child(self.calls as u64).rng();
+        self.calls += 1;
+        let mut output = Vec::with_capacity(input.len());
+        while output.len() < input.len() {
+            if self.exhausted() {
+                // The source ran out, so drain the buffer
+                while output.len() < input.len() && !self.buffer.is_empty() {
+                    let index = rng.gen_range(0..self.buffer.len());
+                    output.push(self.buffer.swap_remove(index));
+                }
+                break;
+            }
+            for example in self.node.process(vec![(); input.len() - output.len()]) {
+                if self.buffer.len() < self.capacity {
+                    self.buffer.push(example);
+                } else {
+                    let index = rng.gen_range(0..self.capacity);
+                    output.push(std::mem::replace(&mut self.buffer[index], example));
+                }
+            }
+        }
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+        self.epoch = epoch;
+        self.calls = 0;
+        self.buffer.clear();
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.node
+            .data_remaining(before)
+            .saturating_add(self.buffer.len())
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        self.node.size_hint(before) + SizeHint::exact(self.buffer.len())
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(vec![
+            NodeState::Index(self.calls),
+            save_held(self.buffer_fns, &self.buffer),
+            self.node.state_dict(),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        self.calls = state[0].index();
+        self.buffer = load_held(self.buffer_fns, &state[1]);
+        self.node.load_state_dict(&state[2]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("ShuffleBuffer")
+            .param("capacity", self.capacity)
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeShuffleBuffer<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Shuffle this node's examples across calls, through a buffer of a capacity
+    fn shuffle_buffer(self, capacity: usize) -> ShuffleBuffer<E, T>;
+}
+
+impl<T, E: Node<Vec<()>, Output = Vec<T>>> ExtendNodeShuffleBuffer<T, E> for E {
+    fn shuffle_buffer(self, capacity: usize) -> ShuffleBuffer<E, T> {
+        ShuffleBuffer::new(self, capacity)
+    }
 }
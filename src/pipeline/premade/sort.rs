@@ -1,5 +1,13 @@
-use crate::pipeline::Node;
-use std::{cmp::Ordering, marker::PhantomData};
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    cmp::Ordering,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::spill::{default_spill_dir, merge_runs, Merge, SpillDir, SpillReader, MAX_FAN_IN};
 
 pub struct Sort<T, F: Fn(&T, &T) -> Ordering> {
     _phantom: PhantomData<T>,
@@ -18,7 +26,7 @@ impl<T, F: Clone + Fn(&T, &T) -> Ordering> Clone for Sort<T, F> {
 impl<T, F: Fn(&T, &T) -> Ordering> Sort<T, F> {
     pub fn new(sort_fn: F) -> Self {
         Sort {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             sort_fn,
         }
     }
@@ -32,3 +40,222 @@ impl<T, F: Fn(&T, &T) -> Ordering> Node<Vec<T>> for Sort<T, F> {
         input
     }
 }
+
+/// Sorted output, either all in memory or merged from sorted runs on disk
+enum Sorted<T, F> {
+    Memory(std::vec::IntoIter<T>),
+    Runs {
+        merge: Merge<T, SpillReader<T>, F>,
+        _dir: SpillDir, // Removes the runs once the merge is dropped
+    },
+}
+
+/// Sorts everything a source node produces, rather than one batch at a time.
+/// The whole source is consumed on the first call, spilling sorted runs to disk whenever `max_in_memory` examples are held, and the runs are merged as the output streams out in chunks the size of each input.
+/// At most `max_fan_in` runs are merged at once, past that they're merged in passes on disk first.
+/// Equal examples keep the order the source produced them in.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut sorted = GlobalSort::new(VecLoader::new(vec![3, 1, 2]), |a: &i32, b: &i32| a.cmp(b));
+/// sorted.reset(Epoch::default());
+/// assert_eq!(sorted.process(vec![(); 2]), vec![1, 2]);
+/// assert_eq!(sorted.process(vec![(); 2]), vec![3]);
+/// ```
+pub struct GlobalSort<N, T, F: Fn(&T, &T) -> Ordering> {
+    node: N,
+    sort_fn: Arc<F>,
+    max_in_memory: usize,
+    max_fan_in: usize,
+    spill_dir: PathBuf,
+    sorted: Option<Sorted<T, F>>, // Set once the source is consumed
+    remaining: usize,
+    delivered: usize,
+    skip: usize, // Examples delivered before a restore, dropped once sorted
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T, F: Fn(&T, &T) -> Ordering> GlobalSort<N, T, F> {
+    pub fn new(node: N, sort_fn: F) -> Self {
+        GlobalSort {
+            node,
+            sort_fn: Arc::new(sort_fn),
+            max_in_memory: 100_000,
+            max_fan_in: MAX_FAN_IN,
+            spill_dir: default_spill_dir(),
+            sorted: None,
+            remaining: 0,
+            delivered: 0,
+            skip: 0,
+        }
+    }
+
+    /// The most examples held in memory while sorting. Past this, sorted runs are spilled to disk
+    pub fn max_in_memory(self, max_in_memory: usize) -> Self {
+        assert!(max_in_memory > 0, "GlobalSort must hold at least 1 example");
+        GlobalSort {
+            max_in_memory,
+            ..self
+        }
+    }
+
+    /// The most sorted runs merged at once, which bounds the files open while merging
+    pub fn max_fan_in(self, max_fan_in: usize) -> Self {
+        assert!(
+            max_fan_in > 1,
+            "GlobalSort must merge at least 2 runs at once"
+        );
+        GlobalSort { max_fan_in, ..self }
+    }
+
+    /// Directory to spill sorted runs into, defaulting to the system temp directory
+    pub fn spill_dir<P: AsRef<Path>>(self, spill_dir: P) -> Self {
+        GlobalSort {
+            spill_dir: spill_dir.as_ref().to_path_buf(),
+            ..self
+        }
+    }
+
+    fn next_sorted(&mut self) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        match self.sorted.as_mut()? {
+            Sorted::Memory(items) => items.next(),
+            Sorted::Runs { merge, .. } => merge.next(),
+        }
+    }
+}
+
+impl<
+        N: Node<Vec<()>, Output = Vec<T>>,
+        T: Serialize + DeserializeOwned,
+        F: Fn(&T, &T) -> Ordering,
+    > GlobalSort<N, T, F>
+{
+    /// Run the source to the end, sorting what it produces
+    fn consume(&mut self, chunk_size: usize) {
+        let mut run = vec![];
+        let mut runs = vec![];
+        let mut dir = None;
+        let mut total = 0;
+        while !self.node.size_hint(SizeHint::exact(0)).is_empty() {
+            let mut output = self.node.process(vec![(); chunk_size]);
+            total += output.len();
+            run.append(&mut output);
+            if run.len() >= self.max_in_memory {
+                run.sort_by(|a, b| (self.sort_fn)(a, b));
+                let dir = dir.get_or_insert_with(|| SpillDir::new(&self.spill_dir));
+                runs.push(dir.write(&run));
+                run.clear();
+            }
+        }
+        run.sort_by(|a, b| (self.sort_fn)(a, b));
+        self.sorted = Some(match dir {
+            None => Sorted::Memory(run.into_iter()),
+            Some(dir) => {
+                if !run.is_empty() {
+                    runs.push(dir.write(&run));
+                }
+                Sorted::Runs {
+                    merge: merge_runs(&dir, runs, self.sort_fn.clone(), self.max_fan_in),
+                    _dir: dir,
+                }
+            }
+        });
+        self.remaining = total;
+        for _ in 0..self.skip.min(total) {
+            self.next_sorted();
+        }
+        self.remaining -= self.skip.min(total);
+        self.skip = 0;
+    }
+}
+
+impl<
+        N: Node<Vec<()>, Output = Vec<T>>,
+        T: Serialize + DeserializeOwned,
+        F: Fn(&T, &T) -> Ordering,
+    > Node<Vec<()>> for GlobalSort<N, T, F>
+{
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        if self.sorted.is_none() {
+            self.consume(input.len().max(1));
+        }
+        let mut output = Vec::with_capacity(input.len().min(self.remaining));
+        while output.len() < input.len() {
+            match self.next_sorted() {
+                Some(item) => output.push(item),
+                None => break,
+            }
+        }
+        self.remaining -= output.len();
+        self.delivered += output.len();
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+        self.sorted = None;
+        self.remaining = 0;
+        self.delivered = 0;
+        self.skip = 0;
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        match self.sorted {
+            Some(_) => self.remaining,
+            None => self.node.data_remaining(before).saturating_sub(self.skip),
+        }
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        match self.sorted {
+            Some(_) => SizeHint::exact(self.remaining),
+            None => {
+                let hint = self.node.size_hint(before);
+                SizeHint {
+                    lower: hint.lower.saturating_sub(self.skip),
+                    upper: hint.upper.map(|u| u.saturating_sub(self.skip)),
+                }
+            }
+        }
+    }
+
+    /// How many sorted examples were delivered. The source is sorted again on load, and those are skipped
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.delivered)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.sorted = None;
+        self.remaining = 0;
+        self.delivered = state.index();
+        self.skip = self.delivered;
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("GlobalSort")
+            .param("max_in_memory", self.max_in_memory)
+            .param("max_fan_in", self.max_fan_in)
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeGlobalSort<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Sort everything this node produces, spilling to disk past `max_in_memory` examples
+    fn global_sort<F: Fn(&T, &T) -> Ordering>(self, sort_fn: F) -> GlobalSort<E, T, F>;
+}
+
+impl<T, E: Node<Vec<()>, Output = Vec<T>>> ExtendNodeGlobalSort<T, E> for E {
+    fn global_sort<F: Fn(&T, &T) -> Ordering>(self, sort_fn: F) -> GlobalSort<E, T, F> {
+        GlobalSort::new(self, sort_fn)
+    }
+}
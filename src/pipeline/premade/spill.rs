@@ -0,0 +1,225 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+static SPILL_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// The most runs merged at once by default, which bounds the files open while merging
+pub(crate) const MAX_FAN_IN: usize = 64;
+
+/// Where spilling nodes put their files when no directory is given
+pub(crate) fn default_spill_dir() -> PathBuf {
+    std::env::temp_dir().join("dataflow")
+}
+
+/// A directory of spill files that belongs to one node, removed when it's dropped
+pub(crate) struct SpillDir {
+    path: PathBuf,
+    files: AtomicUsize,
+}
+
+impl SpillDir {
+    /// Make a fresh directory inside the parent
+    pub(crate) fn new(parent: &Path) -> Self {
+        let path = parent.join(format!(
+            "spill-{}-{}",
+            std::process::id(),
+            SPILL_DIRS.fetch_add(1, AtomicOrdering::SeqCst)
+        ));
+        fs::create_dir_all(&path).expect("Failed to create the spill directory");
+        SpillDir {
+            path,
+            files: AtomicUsize::new(0),
+        }
+    }
+
+    /// Write items to a new file, to be read back in the same order
+    pub(crate) fn write<T: Serialize>(&self, items: &[T]) -> SpillFile<T> {
+        self.write_with(|writer| {
+            for item in items {
+                bincode::serialize_into(&mut *writer, item).expect("Failed to write a spill file");
+            }
+            items.len()
+        })
+    }
+
+    /// Write everything an iterator yields to a new file, without collecting it first
+    pub(crate) fn write_iter<T: Serialize>(&self, items: impl Iterator<Item = T>) -> SpillFile<T> {
+        self.write_with(|writer| {
+            let mut len = 0;
+            for item in items {
+                bincode::serialize_into(&mut *writer, &item).expect("Failed to write a spill file");
+                len += 1;
+            }
+            len
+        })
+    }
+
+    fn write_with<T>(&self, write: impl FnOnce(&mut BufWriter<File>) -> usize) -> SpillFile<T> {
+        let path = self.path.join(format!(
+            "{}.bin",
+            self.files.fetch_add(1, AtomicOrdering::SeqCst)
+        ));
+        let mut writer =
+            BufWriter::new(File::create(&path).expect("Failed to create a spill file"));
+        let len = write(&mut writer);
+        writer.flush().expect("Failed to write a spill file");
+        SpillFile {
+            path,
+            len,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Items written to a spill file, which is removed when it's dropped
+pub(crate) struct SpillFile<T> {
+    path: PathBuf,
+    len: usize,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> SpillFile<T> {
+    /// Stream the items back. The file is removed once the reader is dropped
+    pub(crate) fn read(self) -> SpillReader<T> {
+        SpillReader {
+            reader: BufReader::new(File::open(&self.path).expect("Failed to open a spill file")),
+            remaining: self.len,
+            _file: self,
+        }
+    }
+}
+
+impl<T> Drop for SpillFile<T> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Streams the items of a spill file back
+pub(crate) struct SpillReader<T> {
+    reader: BufReader<File>, // Closed before the file is removed
+    remaining: usize,
+    _file: SpillFile<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for SpillReader<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(bincode::deserialize_from(&mut self.reader).expect("Failed to read a spill file"))
+    }
+}
+
+/// The next item of a sorted run. The heap pops the smallest item, and the earliest run among equal ones
+struct Head<T, F> {
+    item: T,
+    run: usize,
+    cmp: Arc<F>,
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> Ord for Head<T, F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max heap, so both orders are reversed
+        (self.cmp)(&other.item, &self.item).then(other.run.cmp(&self.run))
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> PartialOrd for Head<T, F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> PartialEq for Head<T, F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> Eq for Head<T, F> {}
+
+/// Merges sorted runs into one sorted stream. Equal items come out in the order of their runs, so merging stable sorted runs stays stable
+pub(crate) struct Merge<T, R, F> {
+    heap: BinaryHeap<Head<T, F>>,
+    runs: Vec<R>,
+}
+
+impl<T, R: Iterator<Item = T>, F: Fn(&T, &T) -> Ordering> Merge<T, R, F> {
+    pub(crate) fn new(mut runs: Vec<R>, cmp: Arc<F>) -> Self {
+        let heap = runs
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(run, items)| {
+                items.next().map(|item| Head {
+                    item,
+                    run,
+                    cmp: cmp.clone(),
+                })
+            })
+            .collect();
+        Merge { heap, runs }
+    }
+}
+
+impl<T, R: Iterator<Item = T>, F: Fn(&T, &T) -> Ordering> Iterator for Merge<T, R, F> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let Head { item, run, cmp } = self.heap.pop()?;
+        if let Some(next) = self.runs[run].next() {
+            self.heap.push(Head {
+                item: next,
+                run,
+                cmp,
+            });
+        }
+        Some(item)
+    }
+}
+
+/// Merge sorted runs, at most `fan_in` at a time. Runs are merged into fewer, longer runs on disk until `fan_in` are left, and those are merged as the output is read
+pub(crate) fn merge_runs<T: Serialize + DeserializeOwned, F: Fn(&T, &T) -> Ordering>(
+    dir: &SpillDir,
+    mut runs: Vec<SpillFile<T>>,
+    cmp: Arc<F>,
+    fan_in: usize,
+) -> Merge<T, SpillReader<T>, F> {
+    while runs.len() > fan_in {
+        let mut remaining = runs.into_iter();
+        runs = vec![];
+        loop {
+            let group = remaining
+                .by_ref()
+                .take(fan_in)
+                .map(SpillFile::read)
+                .collect::<Vec<_>>();
+            if group.is_empty() {
+                break;
+            }
+            runs.push(dir.write_iter(Merge::new(group, cmp.clone())));
+        }
+    }
+    Merge::new(runs.into_iter().map(SpillFile::read).collect(), cmp)
+}
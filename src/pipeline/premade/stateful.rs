@@ -1,13 +1,24 @@
-use crate::pipeline::Node;
-use std::marker::PhantomData;
+use crate::pipeline::{Epoch, Node, NodeState};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
 
+/// A node that carries a state between calls. The state lives behind a shared handle, so it can be read or changed from outside while the pipeline runs in a Dataloader
 pub struct Stateful<I, O, S, F: Fn(I, &mut S) -> O, R: Fn(usize) -> usize> {
     _phantom: PhantomData<(I, O)>,
     function: F,
-    state: S,
+    state: Arc<Mutex<S>>,
     remaining: R,
+    on_reset: Option<fn(&mut S)>,
+    state_fns: Option<StateFns<S>>,
 }
 
+/// Functions to save and load a Stateful node's state when checkpointing
+type StateFns<S> = (fn(&S) -> NodeState, fn(&mut S, &NodeState));
+
+/// Clones get their own copy of the state, not a shared one
 impl<I, O, S: Clone, F: Fn(I, &mut S) -> O + Clone, R: Fn(usize) -> usize + Clone> Clone
     for Stateful<I, O, S, F, R>
 {
@@ -15,8 +26,10 @@ impl<I, O, S: Clone, F: Fn(I, &mut S) -> O + Clone, R: Fn(usize) -> usize + Clon
         Self {
             _phantom: self._phantom,
             function: self.function.clone(),
-            state: self.state.clone(),
+            state: Arc::new(Mutex::new(self.state.lock().unwrap().clone())),
             remaining: self.remaining.clone(),
+            on_reset: self.on_reset,
+            state_fns: self.state_fns,
         }
     }
 }
@@ -25,14 +38,24 @@ fn identity_remaining(before: usize) -> usize {
     before
 }
 
+fn save_serde<S: Serialize>(state: &S) -> NodeState {
+    NodeState::Bytes(bincode::serialize(state).expect("Failed to serialize the state"))
+}
+
+fn load_serde<S: DeserializeOwned>(state: &mut S, saved: &NodeState) {
+    *state = bincode::deserialize(saved.bytes()).expect("Failed to deserialize the state");
+}
+
 impl<I, O, S, F: Fn(I, &mut S) -> O> Stateful<I, O, S, F, fn(usize) -> usize> {
     /// Initialize a new stateful node, with a state and a process function.
     pub fn new(state: S, function: F) -> Self {
         Stateful {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             function,
-            state,
+            state: Arc::new(Mutex::new(state)),
             remaining: identity_remaining,
+            on_reset: None,
+            state_fns: None,
         }
     }
 }
@@ -40,22 +63,72 @@ impl<I, O, S, F: Fn(I, &mut S) -> O> Stateful<I, O, S, F, fn(usize) -> usize> {
 impl<I, O, S, F: Fn(I, &mut S) -> O, R: Fn(usize) -> usize> Stateful<I, O, S, F, R> {
     pub fn remaining<N: Fn(usize) -> usize>(self, remaining_fn: N) -> Stateful<I, O, S, F, N> {
         Stateful {
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
             function: self.function,
             state: self.state,
             remaining: remaining_fn,
+            on_reset: self.on_reset,
+            state_fns: self.state_fns,
+        }
+    }
+
+    /// Run this on the state whenever the node is reset, so nothing leaks between epochs
+    pub fn on_reset(self, on_reset: fn(&mut S)) -> Self {
+        Stateful {
+            on_reset: Some(on_reset),
+            ..self
+        }
+    }
+
+    /// Save and load the state with these functions when the pipeline is checkpointed
+    pub fn with_state_dict(self, save: fn(&S) -> NodeState, load: fn(&mut S, &NodeState)) -> Self {
+        Stateful {
+            state_fns: Some((save, load)),
+            ..self
         }
     }
+
+    /// Save and load the whole state with serde when the pipeline is checkpointed
+    pub fn with_serde_state(self) -> Self
+    where
+        S: Serialize + DeserializeOwned,
+    {
+        self.with_state_dict(save_serde::<S>, load_serde::<S>)
+    }
+
+    /// A handle to the state, for reading or changing it while the node runs elsewhere
+    pub fn state_handle(&self) -> Arc<Mutex<S>> {
+        self.state.clone()
+    }
 }
 
 impl<I, O, S, F: Fn(I, &mut S) -> O, R: Fn(usize) -> usize> Node<I> for Stateful<I, O, S, F, R> {
     type Output = O;
 
     fn process(&mut self, input: I) -> Self::Output {
-        (self.function)(input, &mut self.state)
+        (self.function)(input, &mut self.state.lock().unwrap())
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        if let Some(on_reset) = self.on_reset {
+            on_reset(&mut self.state.lock().unwrap());
+        }
     }
 
     fn data_remaining(&self, before: usize) -> usize {
         (self.remaining)(before)
     }
+
+    fn state_dict(&self) -> NodeState {
+        match self.state_fns {
+            Some((save, _)) => save(&self.state.lock().unwrap()),
+            None => NodeState::Empty,
+        }
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        if let Some((_, load)) = self.state_fns {
+            load(&mut self.state.lock().unwrap(), state);
+        }
+    }
 }
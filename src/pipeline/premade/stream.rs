@@ -0,0 +1,311 @@
+use std::marker::PhantomData;
+
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+
+/// Passes on the first `n` examples of the epoch, then nothing
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut take = Take::new(3);
+/// assert_eq!(take.process(vec![1, 2]), vec![1, 2]);
+/// assert_eq!(take.process(vec![3, 4]), vec![3]);
+/// assert_eq!(take.process(vec![5]), Vec::<i32>::new());
+/// ```
+pub struct Take<T> {
+    n: usize,
+    taken: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Take<T> {
+    pub fn new(n: usize) -> Self {
+        Take {
+            n,
+            taken: 0,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Node<Vec<T>> for Take<T> {
+    type Output = Vec<T>;
+
+    fn process(&mut self, mut input: Vec<T>) -> Self::Output {
+        input.truncate(self.n - self.taken);
+        self.taken += input.len();
+        input
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        self.taken = 0;
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        before.min(self.n - self.taken)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        before.min(SizeHint::exact(self.n - self.taken))
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.taken)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.taken = state.index().min(self.n);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Take").param("n", self.n)
+    }
+}
+
+/// Drops the first `n` examples of the epoch
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut skip = Skip::new(3);
+/// assert_eq!(skip.process(vec![1, 2]), Vec::<i32>::new());
+/// assert_eq!(skip.process(vec![3, 4]), vec![4]);
+/// ```
+pub struct Skip<T> {
+    n: usize,
+    skipped: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Skip<T> {
+    pub fn new(n: usize) -> Self {
+        Skip {
+            n,
+            skipped: 0,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Node<Vec<T>> for Skip<T> {
+    type Output = Vec<T>;
+
+    fn process(&mut self, mut input: Vec<T>) -> Self::Output {
+        let skip = (self.n - self.skipped).min(input.len());
+        self.skipped += skip;
+        input.drain(..skip);
+        input
+    }
+
+    fn reset(&mut self, _epoch: Epoch) {
+        self.skipped = 0;
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        before.saturating_sub(self.n - self.skipped)
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        let left = self.n - self.skipped;
+        SizeHint {
+            lower: before.lower.saturating_sub(left),
+            upper: before.upper.map(|u| u.saturating_sub(left)),
+        }
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::Index(self.skipped)
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        self.skipped = state.index().min(self.n);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Skip").param("n", self.n)
+    }
+}
+
+/// Runs a source node through several passes per epoch, resetting it between them. Each pass after the first is reset with a child of the epoch, so random sources give a new order every pass.
+/// With no number of passes, the source cycles forever.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut upsampled = VecLoader::new(vec![1, 2, 3]).repeat(2);
+/// upsampled.reset(Epoch::default());
+/// assert_eq!(upsampled.data_remaining(0), 6);
+/// assert_eq!(upsampled.process(vec![(); 4]).len(), 4);
+/// ```
+pub struct Repeat<N, T> {
+    node: N,
+    times: Option<usize>, // None cycles forever
+    pass: usize,
+    produced: usize,     // Examples produced this pass
+    pass_size: SizeHint, // Size of a full pass, taken when the node is reset
+    pass_len: usize,     // Estimated size of a full pass
+    epoch: Epoch,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T> Repeat<N, T> {
+    /// Run the node `times` times per epoch
+    pub fn new(node: N, times: usize) -> Self {
+        Self::with_times(node, Some(times))
+    }
+
+    /// Run the node over and over, never ending the epoch unless the node is empty
+    pub fn cycle(node: N) -> Self {
+        Self::with_times(node, None)
+    }
+
+    fn with_times(node: N, times: Option<usize>) -> Self {
+        let (pass_size, pass_len) = (node.size_hint(SizeHint::unknown()), node.data_remaining(0));
+        Repeat {
+            node,
+            times,
+            pass: 0,
+            produced: 0,
+            pass_size,
+            pass_len,
+            epoch: Epoch::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// The epoch the node is reset with for a pass
+    fn pass_epoch(&self, pass: usize) -> Epoch {
+        match pass {
+            0 => self.epoch,
+            _ => self.epoch.child(pass as u64),
+        }
+    }
+
+    /// Full passes left after the current one, None if they never run out
+    fn passes_left(&self) -> Option<usize> {
+        self.times.map(|t| t.saturating_sub(self.pass + 1))
+    }
+
+    /// Start the next pass if there is one. A pass that produced nothing ends the epoch, so filtered out sources don't cycle forever
+    fn next_pass(&mut self) -> bool {
+        if self.passes_left() == Some(0) || self.produced == 0 {
+            return false;
+        }
+        self.pass += 1;
+        self.produced = 0;
+        self.node.reset(self.pass_epoch(self.pass));
+        true
+    }
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T> Node<Vec<()>> for Repeat<N, T> {
+    type Output = Vec<T>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        if self.times == Some(0) {
+            return vec![];
+        }
+        let mut output = Vec::with_capacity(input.len());
+        while output.len() < input.len() {
+            if self.node.size_hint(SizeHint::exact(0)).is_empty() && !self.next_pass() {
+                break;
+            }
+            let mut items = self.node.process(vec![(); input.len() - output.len()]);
+            self.produced += items.len();
+            output.append(&mut items);
+        }
+        output
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.epoch = epoch;
+        self.pass = 0;
+        self.produced = 0;
+        self.node.reset(epoch);
+        self.pass_size = self.node.size_hint(SizeHint::unknown());
+        self.pass_len = self.node.data_remaining(0);
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        if self.times == Some(0) {
+            return 0;
+        }
+        let current = self.node.data_remaining(before);
+        match self.passes_left() {
+            Some(left) => self.pass_len.saturating_mul(left).saturating_add(current),
+            None if self.pass_size.is_empty() => 0,
+            None => usize::MAX,
+        }
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        if self.times == Some(0) {
+            return SizeHint::exact(0);
+        }
+        let current = self.node.size_hint(before);
+        match self.passes_left() {
+            Some(left) => {
+                current
+                    + SizeHint {
+                        lower: self.pass_size.lower.saturating_mul(left),
+                        upper: self.pass_size.upper.and_then(|u| u.checked_mul(left)),
+                    }
+            }
+            None if self.pass_size.is_empty() => SizeHint::exact(0),
+            None => SizeHint {
+                lower: usize::MAX,
+                upper: None,
+            },
+        }
+    }
+
+    fn state_dict(&self) -> NodeState {
+        NodeState::List(vec![
+            NodeState::Index(self.pass),
+            NodeState::Index(self.produced),
+            self.node.state_dict(),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        self.pass = state[0].index();
+        self.produced = state[1].index();
+        if self.pass > 0 {
+            self.node.reset(self.pass_epoch(self.pass));
+        }
+        self.node.load_state_dict(&state[2]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        match self.times {
+            Some(times) => NodeDescription::new("Repeat").param("times", times),
+            None => NodeDescription::new("Cycle"),
+        }
+        .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeRepeat<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Run this node `times` times per epoch
+    fn repeat(self, times: usize) -> Repeat<E, T>;
+    /// Run this node over and over, for an infinite stream
+    fn cycle(self) -> Repeat<E, T>;
+}
+
+impl<T, E: Node<Vec<()>, Output = Vec<T>>> ExtendNodeRepeat<T, E> for E {
+    fn repeat(self, times: usize) -> Repeat<E, T> {
+        Repeat::new(self, times)
+    }
+
+    fn cycle(self) -> Repeat<E, T> {
+        Repeat::cycle(self)
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::held::{load_held, save_held, serde_fns, HeldFns};
+use crate::pipeline::{Epoch, Node, NodeDescription, NodeState, Profile, SizeHint};
+
+/// Slides a window over the examples a source node produces, turning them into overlapping (or spaced out) sequences.
+/// Each call pulls as many examples from the source as it was given inputs. Examples left over at the end of a call are carried into the next one, so windows span call boundaries.
+///
+/// Partial windows at the end of the source are dropped by default. When kept, they come out on the call that finds the source empty.
+/// Use `with_serde_state` to save the examples carried between calls in checkpoints, so a restored node makes exactly the same windows.
+///
+/// ### Example
+/// ```
+/// use dataflow::prelude::*;
+///
+/// let mut windows = VecLoader::new(vec![1, 2, 3, 4, 5, 6]).window(3).stride(2);
+/// windows.reset(Epoch::default());
+/// assert_eq!(windows.process(vec![(); 4]), vec![vec![1, 2, 3]]);
+/// assert_eq!(windows.process(vec![(); 2]), vec![vec![3, 4, 5]]);
+/// ```
+pub struct Window<N, T> {
+    node: N,
+    size: usize,
+    stride: usize,
+    drop_partial: bool,
+    tail: VecDeque<T>, // Examples from the start of the next window on
+    skip: usize, // Examples to skip before the next window starts, when the stride is larger than the window
+    tail_fns: Option<HeldFns<T>>,
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T> Window<N, T> {
+    /// Windows of a size over the node's examples, moving one example at a time
+    pub fn new(node: N, size: usize) -> Self {
+        assert!(size > 0, "Window size must be at least 1");
+        Window {
+            node,
+            size,
+            stride: 1,
+            drop_partial: true,
+            tail: VecDeque::new(),
+            skip: 0,
+            tail_fns: None,
+        }
+    }
+
+    /// How far each window starts after the last one
+    pub fn stride(self, stride: usize) -> Self {
+        assert!(stride > 0, "Window stride must be at least 1");
+        Window { stride, ..self }
+    }
+
+    /// Whether to drop the windows at the end of the source that are shorter than the size
+    pub fn drop_partial(self, drop_partial: bool) -> Self {
+        Window {
+            drop_partial,
+            ..self
+        }
+    }
+
+    /// Save the examples carried between calls in checkpoints with serde, so windows spanning a checkpoint aren't lost
+    pub fn with_serde_state(self) -> Self
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        Window {
+            tail_fns: Some(serde_fns()),
+            ..self
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.node.size_hint(SizeHint::exact(0)).is_empty()
+    }
+
+    /// Move the start of the next window forward by the stride
+    fn advance(&mut self) {
+        let drained = self.stride.min(self.tail.len());
+        self.tail.drain(..drained);
+        self.skip = self.stride - drained;
+    }
+
+    /// Windows that can be made out of this many more examples
+    fn windows(&self, examples: usize) -> usize {
+        let examples = examples
+            .saturating_sub(self.skip)
+            .saturating_add(self.tail.len());
+        if self.drop_partial {
+            match examples >= self.size {
+                true => (examples - self.size) / self.stride + 1,
+                false => 0,
+            }
+        } else {
+            examples.div_ceil(self.stride)
+        }
+    }
+}
+
+impl<N: Node<Vec<()>, Output = Vec<T>>, T: Clone> Node<Vec<()>> for Window<N, T> {
+    type Output = Vec<Vec<T>>;
+
+    fn process(&mut self, input: Vec<()>) -> Self::Output {
+        let mut windows = vec![];
+        if !self.exhausted() {
+            let examples = self.node.process(input);
+            let skipped = self.skip.min(examples.len());
+            self.skip -= skipped;
+            self.tail.extend(examples.into_iter().skip(skipped));
+            while self.tail.len() >= self.size {
+                windows.push(self.tail.iter().take(self.size).cloned().collect());
+                self.advance();
+            }
+        }
+        if self.exhausted() {
+            if self.drop_partial {
+                self.tail.clear();
+            }
+            // The source ran out, so flush what's left
+            while !self.tail.is_empty() {
+                windows.push(self.tail.iter().cloned().collect());
+                self.advance();
+            }
+        }
+        windows
+    }
+
+    fn reset(&mut self, epoch: Epoch) {
+        self.node.reset(epoch);
+        self.tail.clear();
+        self.skip = 0;
+    }
+
+    fn data_remaining(&self, before: usize) -> usize {
+        self.windows(self.node.data_remaining(before))
+    }
+
+    fn size_hint(&self, before: SizeHint) -> SizeHint {
+        let hint = self.node.size_hint(before);
+        SizeHint {
+            lower: self.windows(hint.lower),
+            upper: hint.upper.map(|u| self.windows(u)),
+        }
+    }
+
+    fn state_dict(&self) -> NodeState {
+        // Only copy the tail out when it's going to be saved
+        let tail = match self.tail_fns {
+            Some(_) => self.tail.iter().cloned().collect(),
+            None => vec![],
+        };
+        NodeState::List(vec![
+            NodeState::Index(self.skip),
+            save_held(self.tail_fns, &tail),
+            self.node.state_dict(),
+        ])
+    }
+
+    fn load_state_dict(&mut self, state: &NodeState) {
+        let state = state.list();
+        self.skip = state[0].index();
+        self.tail = load_held(self.tail_fns, &state[1]).into();
+        self.node.load_state_dict(&state[2]);
+    }
+
+    fn describe(&self) -> NodeDescription {
+        NodeDescription::new("Window")
+            .param("size", self.size)
+            .param("stride", self.stride)
+            .param("drop_partial", self.drop_partial)
+            .child(self.node.describe())
+    }
+
+    fn profiles(&self) -> Vec<Profile> {
+        self.node.profiles()
+    }
+}
+
+pub trait ExtendNodeWindow<T, E: Node<Vec<()>, Output = Vec<T>>> {
+    /// Slide a window of a size over this node's examples
+    fn window(self, size: usize) -> Window<E, T>;
+}
+
+impl<T, E: Node<Vec<()>, Output = Vec<T>>> ExtendNodeWindow<T, E> for E {
+    fn window(self, size: usize) -> Window<E, T> {
+        Window::new(self, size)
+    }
+}
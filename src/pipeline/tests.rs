@@ -1,4 +1,11 @@
-use std::{marker::PhantomData, thread};
+use std::{
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Barrier,
+    },
+    thread,
+};
 
 use crate::pipeline::*;
 
@@ -12,6 +19,9 @@ fn convert_to_int(inp: Vec<String>) -> Vec<i32> {
 fn greet(inp: Vec<String>) -> Vec<String> {
     inp.into_iter().map(|i| format!("Hello {}", i)).collect()
 }
+fn parse_ints(inp: Vec<String>) -> Result<Vec<i32>, std::num::ParseIntError> {
+    inp.into_iter().map(|i| i.parse::<i32>()).collect()
+}
 fn concat_strings(inp: Vec<(String, String)>) -> Vec<String> {
     inp.into_iter()
         .map(|(a, b)| format!("{}{}", a, b))
@@ -44,7 +54,7 @@ fn test_pair_pipeline() {
         )
         .chain(|(a, b): (Vec<String>, Vec<String>)| {
             a.into_iter()
-                .zip(b.into_iter())
+                .zip(b)
                 .collect::<Vec<(String, String)>>()
         })
         .chain(concat_strings)
@@ -71,6 +81,28 @@ fn test_pair_pipeline() {
     );
 }
 
+#[test]
+fn test_concurrent_pair_pipeline() {
+    // Both branches wait on the same barrier, so this only finishes if they run at the same time
+    let barrier = Arc::new(Barrier::new(2));
+    let (barrier1, barrier2) = (barrier.clone(), barrier);
+    let mut pipeline = add_ten.split_concurrent(
+        move |nums: Vec<i32>| {
+            barrier1.wait();
+            nums.into_iter().map(|i| i.to_string()).collect::<Vec<_>>()
+        },
+        move |nums: Vec<i32>| {
+            barrier2.wait();
+            nums.into_iter().map(|i| i * 2).collect::<Vec<_>>()
+        },
+    );
+
+    assert_eq!(
+        Node::process(&mut pipeline, vec![1, 2]),
+        (vec!["11".to_string(), "12".to_string()], vec![22, 24])
+    );
+}
+
 #[test]
 fn test_map_reduce_pipeline() {
     let mut pipeline = MapReduce::new(
@@ -94,6 +126,76 @@ fn test_map_reduce_pipeline() {
     )
 }
 
+#[test]
+fn test_fallible_pipeline() {
+    // Errors from earlier stages get converted into the last stage's error type
+    let mut pipeline = parse_ints.then(add_ten).try_chain(
+        |nums: Vec<i32>| -> Result<Vec<i32>, Box<dyn std::error::Error>> {
+            if nums.iter().any(|n| *n > 100) {
+                Err("Number too big!".into())
+            } else {
+                Ok(nums)
+            }
+        },
+    );
+
+    assert_eq!(
+        pipeline
+            .try_process(vec!["12".to_string(), "3".to_string()])
+            .unwrap(),
+        vec![22, 13]
+    );
+    // Errors from any stage short circuit
+    assert!(pipeline
+        .try_process(vec!["12".to_string(), "three".to_string()])
+        .is_err());
+    assert!(pipeline
+        .try_process(vec!["12".to_string(), "300".to_string()])
+        .is_err());
+
+    // Errors are collected per sample when a TryMap is run as a regular node
+    let mut pipeline = (|inp: Vec<String>| inp).try_map(|i: String| i.parse::<i32>());
+    let outputs = Node::process(&mut pipeline, vec!["1".to_string(), "two".to_string()]);
+    assert_eq!(outputs[0], Ok(1));
+    assert!(outputs[1].is_err());
+}
+
+#[test]
+fn test_par_map_pipeline() {
+    let mut pipeline = add_ten
+        .par_map(|i: i32| i.to_string())
+        .chain(ParFilterMap::new(|s: String| (s.len() > 2).then_some(s)).threads(3));
+
+    let inputs = (0..1000).collect::<Vec<i32>>();
+    assert_eq!(
+        Node::process(&mut pipeline, inputs),
+        (100..1010).map(|i| i.to_string()).collect::<Vec<_>>()
+    );
+
+    // Profiles of the mapped node show through, counting every worker's calls
+    let mut pipeline = ParMap::new((|v: Vec<i32>| v).profile("inner")).threads(2);
+    assert_eq!(pipeline.process(vec![vec![1], vec![2, 3], vec![]]).len(), 3);
+    let profiles = pipeline.profiles();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(profiles[0].stats().calls, 3);
+    assert_eq!(profiles[0].stats().items_in, 3);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_async_pipeline() {
+    // Sync nodes run on the blocking pool
+    let mut node = Blocking::new(add_ten.map(|i: i32| i.to_string()));
+    assert_eq!(
+        node.async_process(vec![1, 2]).await,
+        vec!["11".to_string(), "12".to_string()]
+    );
+
+    // Async closures are nodes too
+    let mut sum = |nums: Vec<i32>| async move { nums.into_iter().sum::<i32>() };
+    assert_eq!(sum.async_process(vec![1, 2, 3]).await, 6);
+}
+
 struct PipelineHolder<I, N: Node<I>> {
     pub pipeline: Option<N>,
     _phantom: PhantomData<I>,
@@ -113,3 +215,894 @@ where
     pipeline_holder.pipeline = Some(pipeline);
     output
 }
+
+#[test]
+fn test_describe_pipeline() {
+    let pipeline = VecLoader::new(vec![1, 2, 3])
+        .chain(add_ten)
+        .map(|i: i32| i.to_string())
+        .split(greet, convert_to_int.chain(Batch::new(2)));
+    let description = Node::describe(&pipeline);
+
+    // Nested chains are flattened and pairs keep their branches
+    assert_eq!(
+        description.to_text(),
+        "Chain
+  VecLoader(elements=3, shuffle=false)
+  add_ten
+  Map
+    closure
+  Duplicator
+  Pair [parallel]
+    greet
+    Chain
+      convert_to_int
+      Batch(batch_size=2)
+"
+    );
+
+    // The duplicator feeds both branches, and each stage feeds the next
+    let dot = description.to_dot();
+    assert!(dot.starts_with("digraph pipeline {"));
+    for edge in [
+        "n1 -> n2",
+        "n2 -> n4",
+        "n4 -> n5",
+        "n5 -> n7",
+        "n5 -> n9",
+        "n9 -> n10",
+    ] {
+        assert!(dot.contains(edge), "missing {edge} in\n{dot}");
+    }
+}
+
+#[test]
+fn test_dyn_pipeline() {
+    let build = |add: bool, batch: bool| {
+        let pipeline = DynPipeline::new(VecLoader::new((0..100).collect::<Vec<i32>>()))
+            .add_stage_if(add, add_ten)
+            .add_stage(|nums: Vec<i32>| nums.into_iter().map(|n| n as i64).collect::<Vec<_>>());
+        if batch {
+            pipeline.add_stage(Batch::new(10)).build()
+        } else {
+            pipeline.add_stage(Batch::new(1)).build()
+        }
+    };
+
+    // Stages are picked at runtime and the result has a nameable type
+    let pipelines: Vec<BoxedNode<Vec<()>, Vec<Vec<i64>>>> =
+        vec![build(true, true), build(false, false)];
+    let mut outputs = pipelines
+        .into_iter()
+        .zip([10, 100])
+        .map(|(mut p, batches)| {
+            p.reset(Epoch::default());
+            assert_eq!(p.data_remaining(usize::MAX), batches);
+            p.run(30)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(outputs[0].len(), 10);
+    assert_eq!(outputs[0][0][0], 10);
+    assert_eq!(outputs[1].len(), 100);
+    assert_eq!(outputs.pop().unwrap()[0], vec![0]);
+
+    // Boxing keeps the description of the inner pipeline
+    let boxed = VecLoader::new(vec![1]).chain(add_ten).boxed();
+    assert_eq!(Node::describe(&boxed).children.len(), 2);
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn test_config_pipeline() {
+    let config = |stages: &str| -> PipelineConfig {
+        serde_json::from_str(&format!(r#"{{"stages": [{stages}]}}"#)).unwrap()
+    };
+    let registry = NodeRegistry::premade().register::<Vec<usize>, _, _>("AddN", |n: usize| {
+        move |nums: Vec<usize>| nums.into_iter().map(|i| i + n).collect::<Vec<_>>()
+    });
+
+    let loader = r#"{"node": "VecLoader", "params": {"elements": [3, 1, 2]}}"#;
+    let pipeline = registry
+        .build::<Vec<()>, Vec<Vec<usize>>>(&config(&format!(
+            r#"{loader}, {{"node": "AddN", "params": 10}}, {{"node": "Sort", "params": {{"descending": true}}}}, {{"node": "Batch", "params": {{"batch_size": 2}}}}"#
+        )))
+        .unwrap();
+    assert_eq!(pipeline.run(10), vec![vec![13, 12], vec![11]]);
+
+    // Errors point at the stage that went wrong
+    let error = |stages: String| {
+        registry
+            .build::<Vec<()>, Vec<usize>>(&config(&stages))
+            .err()
+            .unwrap()
+    };
+    assert!(matches!(
+        error(format!(r#"{loader}, {{"node": "Missing"}}"#)),
+        ConfigError::UnknownNode { stage: 1, .. }
+    ));
+    match error(format!(r#"{loader}, {{"node": "Filter"}}"#)) {
+        ConfigError::TypeMismatch {
+            stage: 1,
+            input,
+            accepted,
+            ..
+        } => {
+            assert!(input.contains("usize"));
+            assert_eq!(accepted.len(), 3);
+        }
+        e => panic!("Expected a type mismatch, got {e}"),
+    }
+    assert!(matches!(
+        error(format!(
+            r#"{loader}, {{"node": "Batch", "params": {{"size": 2}}}}"#
+        )),
+        ConfigError::Params { stage: 1, .. }
+    ));
+    assert!(matches!(
+        error(format!(
+            r#"{loader}, {{"node": "Batch", "params": {{"batch_size": 2}}}}"#
+        )),
+        ConfigError::OutputMismatch { .. }
+    ));
+
+    // Missing files fail the build instead of panicking
+    let missing = error(
+        r#"{"node": "FileLoader", "params": {"directory": "missing_directory"}}"#.to_string(),
+    );
+    assert!(matches!(missing, ConfigError::Params { stage: 0, .. }));
+    assert!(missing.to_string().contains("missing_directory"));
+    assert!(matches!(
+        registry.build::<Vec<usize>, Vec<String>>(&config(
+            r#"{"node": "KeyedLoader", "params": {"files": ["missing.txt"], "delimiter": "\n"}}"#
+        )),
+        Err(ConfigError::Params { stage: 0, .. })
+    ));
+}
+
+#[test]
+fn test_weighted_selector() {
+    let selector = |exhaustion: Exhaustion, seed: u64| {
+        let mut selector = WeightedSelector::new(Mixture::Weights(vec![3., 1.]))
+            .add_node(VecLoader::new((0..1000).collect::<Vec<usize>>()))
+            .add_node(VecLoader::new((1000..1100).collect::<Vec<usize>>()))
+            .exhaustion(exhaustion)
+            .seed(seed);
+        selector.reset(Epoch::default());
+        selector
+    };
+    let small = |samples: &[usize]| samples.iter().filter(|s| **s >= 1000).count();
+
+    // Samples are mixed by weight, and the seed decides the order
+    let first = selector(Exhaustion::Stop, 0).run(50);
+    assert_eq!(first, selector(Exhaustion::Stop, 0).run(50));
+    assert_ne!(first, selector(Exhaustion::Stop, 1).run(50));
+    assert!((50..150).contains(&small(&first[..400])));
+
+    // Stop ends at the first exhausted child, Drop keeps going until all are empty
+    assert_eq!(small(&first), 100);
+    assert!(first.len() < 1100);
+    let dropped = selector(Exhaustion::Drop, 0).run(50);
+    assert_eq!(dropped.len(), 1100);
+
+    // Restart runs until every child ran out once, so the small child repeats
+    let restarted = selector(Exhaustion::Restart, 0).run(50);
+    assert_eq!(restarted.len() - small(&restarted), 1000);
+    assert!(small(&restarted) > 100);
+
+    // Resuming from a checkpoint continues the same stream
+    let mut node = selector(Exhaustion::Restart, 0);
+    let _ = node.process(vec![(); 700]);
+    let state = node.state_dict();
+    let rest = node.run(50);
+    let mut resumed = selector(Exhaustion::Restart, 0);
+    resumed.load_state_dict(&state);
+    assert_eq!(resumed.run(50), rest);
+
+    // At temperature 1 children are weighted by size, so both run out at the same time
+    let mut tempered = WeightedSelector::new(Mixture::Temperature(1.))
+        .add_node(VecLoader::new(vec![0; 1000]))
+        .add_node(VecLoader::new(vec![0; 10]));
+    tempered.reset(Epoch::default());
+    assert_eq!(tempered.data_remaining(0), 1010);
+}
+
+#[test]
+#[should_panic(expected = "temperature must be finite and positive")]
+fn test_weighted_selector_zero_temperature() {
+    WeightedSelector::<(), usize>::new(Mixture::Temperature(0.));
+}
+
+#[test]
+#[should_panic(expected = "has 2 weights but 1 nodes")]
+fn test_weighted_selector_missing_node() {
+    WeightedSelector::new(Mixture::Weights(vec![1., 1.]))
+        .add_node(VecLoader::new(vec![0usize]))
+        .reset(Epoch::default());
+}
+
+#[test]
+fn test_size_hints() {
+    let numbers = || VecLoader::new((0..100).collect::<Vec<usize>>());
+
+    // Loaders and maps know exactly how much is left
+    let mapped = numbers().map(|i: usize| i * 2);
+    assert_eq!(mapped.size_hint(SizeHint::unknown()), SizeHint::exact(100));
+
+    // Filtering keeps the upper bound, flattening and reducing lose it
+    let mut filtered = numbers().filter(|i: &usize| i.is_multiple_of(2));
+    let hint = filtered.size_hint(SizeHint::unknown());
+    assert_eq!((hint.lower, hint.upper), (0, Some(100)));
+    assert!(!hint.is_exact());
+    let flattened = numbers().map(|i: usize| vec![i; i % 3]).flatten();
+    assert_eq!(
+        flattened.size_hint(SizeHint::unknown()),
+        SizeHint::unknown()
+    );
+    let reduced = numbers().chain(MapReduce::new(
+        |i: usize| vec![(i % 7, i)],
+        |(_, v): (usize, Vec<usize>)| v,
+    ));
+    assert_eq!(reduced.size_hint(SizeHint::unknown()), SizeHint::unknown());
+
+    // Once the source runs out, every bound drops to zero
+    assert_eq!(filtered.process(vec![(); 100]).len(), 50);
+    assert_eq!(filtered.size_hint(SizeHint::unknown()), SizeHint::exact(0));
+
+    // Pairs are bounded by their smaller side, selectors add their children up
+    let paired = numbers()
+        .chain(Duplicator::default())
+        .pair(Filter::new(|i: &usize| *i > 10), Map::new(|i: usize| i + 1));
+    let hint = paired.size_hint(SizeHint::unknown());
+    assert_eq!((hint.lower, hint.upper), (0, Some(100)));
+    let selector = BalancedSelector::default()
+        .add_node(numbers())
+        .add_node(numbers().filter(|i: &usize| *i > 10));
+    let hint = selector.size_hint(SizeHint::unknown());
+    assert_eq!((hint.lower, hint.upper), (100, Some(200)));
+
+    // Every batch has at least one example, so running doesn't stop while a partial batch is left
+    let batched = numbers().chain(Batch::new(8));
+    let hint = batched.size_hint(SizeHint::unknown());
+    assert_eq!((hint.lower, hint.upper), (13, Some(100)));
+    assert_eq!(batched.run(96).concat(), (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_cache() {
+    fn load_epoch<N: Node<Vec<()>, Output = Vec<usize>>>(node: &mut N, index: usize) -> Vec<usize> {
+        node.reset(Epoch::new(index, 0));
+        let mut output = vec![];
+        while !node.size_hint(SizeHint::unknown()).is_empty() {
+            output.extend(node.process(vec![(); 30]));
+        }
+        output
+    }
+    let calls = Arc::new(AtomicUsize::new(0));
+    let doubled = || {
+        let calls = calls.clone();
+        VecLoader::new((0..100).collect::<Vec<usize>>()).map(move |i: usize| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            i * 2
+        })
+    };
+    let expected = (0..100).map(|i| i * 2).collect::<Vec<_>>();
+
+    // The first epoch runs the node, later ones replay it in a new order
+    let mut cache = doubled().cache().reshuffle(true);
+    assert_eq!(load_epoch(&mut cache, 0), expected);
+    let mut replayed = load_epoch(&mut cache, 1);
+    assert_ne!(replayed, expected);
+    replayed.sort_unstable();
+    assert_eq!(replayed, expected);
+    assert_eq!(calls.load(Ordering::SeqCst), 100);
+
+    // An epoch cut short isn't committed, so the next one records again
+    let mut cache = doubled().cache();
+    cache.reset(Epoch::new(0, 0));
+    let _ = cache.process(vec![(); 30]);
+    assert_eq!(load_epoch(&mut cache, 1), expected);
+    assert_eq!(calls.load(Ordering::SeqCst), 230);
+    assert_eq!(load_epoch(&mut cache, 2), expected);
+    assert_eq!(calls.load(Ordering::SeqCst), 230);
+
+    // A disk cache is reused by later runs until the fingerprint changes. Recordings are committed when the next epoch starts
+    let dir = std::env::temp_dir().join("dataflow_cache_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    let disk_cache =
+        |version: &'static str| Cache::new(doubled()).on_disk(&dir, move || version.to_string());
+    let mut cache = disk_cache("v1");
+    assert_eq!(load_epoch(&mut cache, 0), expected);
+    cache.reset(Epoch::new(1, 0));
+    assert_eq!(calls.load(Ordering::SeqCst), 330);
+    let mut cache = disk_cache("v1");
+    assert_eq!(load_epoch(&mut cache, 0), expected);
+    assert_eq!(load_epoch(&mut cache, 1), expected);
+    assert_eq!(calls.load(Ordering::SeqCst), 330);
+    let mut cache = disk_cache("v2");
+    assert_eq!(load_epoch(&mut cache, 0), expected);
+    cache.reset(Epoch::new(1, 0));
+    assert_eq!(calls.load(Ordering::SeqCst), 430);
+
+    // Checkpoints taken while replaying resume in the same place
+    let mut cache = disk_cache("v2").reshuffle(true);
+    cache.reset(Epoch::new(3, 0));
+    let _ = cache.process(vec![(); 40]);
+    let state = cache.state_dict();
+    let rest = cache.process(vec![(); 100]);
+    let mut resumed = disk_cache("v2").reshuffle(true);
+    resumed.reset(Epoch::new(3, 0));
+    resumed.load_state_dict(&state);
+    assert_eq!(resumed.process(vec![(); 100]), rest);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_prefetch() {
+    let loaded = Arc::new(AtomicUsize::new(0));
+    let numbers = || {
+        let loaded = loaded.clone();
+        VecLoader::new((0..100).collect::<Vec<usize>>()).chain(move |batch: Vec<usize>| {
+            loaded.fetch_add(batch.len(), Ordering::SeqCst);
+            batch
+        })
+    };
+
+    // The output is the same as without prefetching, regrouped to each input's size
+    let mut prefetched = numbers().prefetch(2);
+    prefetched.reset(Epoch::default());
+    assert_eq!(
+        prefetched.process(vec![(); 10]),
+        (0..10).collect::<Vec<_>>()
+    );
+    assert_eq!(prefetched.data_remaining(0), 90);
+
+    // The loader runs ahead on its own thread until the queue is full
+    let start = std::time::Instant::now();
+    while loaded.load(Ordering::SeqCst) < 30 && start.elapsed().as_secs() < 5 {
+        thread::yield_now();
+    }
+    assert!(loaded.load(Ordering::SeqCst) >= 30);
+    assert_eq!(
+        prefetched.size_hint(SizeHint::unknown()),
+        SizeHint::exact(90)
+    );
+
+    // Checkpoints count what was delivered, not what was loaded ahead
+    assert_eq!(
+        prefetched.process(vec![(); 15]),
+        (10..25).collect::<Vec<_>>()
+    );
+    let state = prefetched.state_dict();
+    let rest = prefetched.run(20);
+    assert_eq!(rest, (25..100).collect::<Vec<_>>());
+    let mut resumed = numbers().prefetch(2);
+    resumed.reset(Epoch::default());
+    resumed.load_state_dict(&state);
+    assert_eq!(resumed.size_hint(SizeHint::unknown()), SizeHint::exact(75));
+    assert_eq!(resumed.run(20), rest);
+
+    // Resetting throws away anything loaded ahead
+    let mut prefetched = numbers().prefetch(4);
+    prefetched.reset(Epoch::default());
+    let _ = prefetched.process(vec![(); 10]);
+    prefetched.reset(Epoch::new(1, 0));
+    assert_eq!(prefetched.data_remaining(0), 100);
+    assert_eq!(prefetched.run(7), (0..100).collect::<Vec<_>>());
+
+    // Size queries don't wait on a chunk the thread is loading
+    let (release, gate) = std::sync::mpsc::channel::<()>();
+    let mut prefetched = VecLoader::new((0..10).collect::<Vec<usize>>())
+        .chain(move |batch: Vec<usize>| {
+            gate.recv().unwrap();
+            batch
+        })
+        .prefetch(1);
+    prefetched.reset(Epoch::default());
+    release.send(()).unwrap();
+    assert_eq!(prefetched.process(vec![(); 5]), (0..5).collect::<Vec<_>>());
+    thread::sleep(std::time::Duration::from_millis(20));
+    assert_eq!(prefetched.data_remaining(0), 5);
+    assert_eq!(prefetched.size_hint(SizeHint::unknown()), SizeHint::exact(5));
+    assert_eq!(prefetched.describe().children.len(), 1);
+    release.send(()).unwrap();
+    assert_eq!(prefetched.process(vec![(); 5]), (5..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_window() {
+    let numbers = |n: usize| VecLoader::new((0..n).collect::<Vec<usize>>());
+    let empty = Vec::<Vec<usize>>::new();
+
+    // Windows span call boundaries
+    let mut windows = numbers(4).window(3);
+    windows.reset(Epoch::default());
+    assert_eq!(windows.process(vec![(); 2]), empty);
+    assert_eq!(windows.data_remaining(0), 2);
+    assert_eq!(
+        windows.process(vec![(); 2]),
+        vec![vec![0, 1, 2], vec![1, 2, 3]]
+    );
+
+    // Strides longer than the window skip examples, even across calls
+    let mut windows = numbers(8).window(2).stride(5);
+    windows.reset(Epoch::default());
+    assert_eq!(windows.process(vec![(); 3]), vec![vec![0, 1]]);
+    assert_eq!(windows.process(vec![(); 5]), vec![vec![5, 6]]);
+
+    // Partial windows come out once the source runs out, not when a chunk is filtered away
+    let mut windows = numbers(20)
+        .filter(|i: &usize| !(5..10).contains(i))
+        .window(4)
+        .stride(4)
+        .drop_partial(false);
+    windows.reset(Epoch::default());
+    assert_eq!(windows.process(vec![(); 5]), vec![vec![0, 1, 2, 3]]);
+    assert_eq!(windows.process(vec![(); 5]), empty);
+    assert_eq!(windows.process(vec![(); 5]), vec![vec![4, 10, 11, 12]]);
+    assert_eq!(
+        windows.process(vec![(); 5]),
+        vec![vec![13, 14, 15, 16], vec![17, 18, 19]]
+    );
+    assert_eq!(windows.size_hint(SizeHint::exact(0)), SizeHint::exact(0));
+
+    // Size hints count the windows the loader's examples make
+    let mut windows = numbers(100).window(10).stride(5);
+    assert_eq!(windows.size_hint(SizeHint::unknown()), SizeHint::exact(19));
+    windows.reset(Epoch::default());
+    assert_eq!(windows.run(7).len(), 19);
+
+    // With serde state, a restored node makes exactly the same windows as one that kept going
+    let windows = || numbers(30).window(4).stride(6).with_serde_state();
+    let mut original = windows();
+    original.reset(Epoch::default());
+    let _ = original.process(vec![(); 9]);
+    let state = original.state_dict();
+    let rest = original.run(5);
+    let mut restored = windows();
+    restored.reset(Epoch::default());
+    restored.load_state_dict(&state);
+    assert_eq!(restored.run(5), rest);
+    assert_eq!(rest[0], vec![6, 7, 8, 9]);
+}
+
+#[test]
+fn test_bucket_batch() {
+    // Examples wait in their bucket until it fills, across calls
+    let batcher = || {
+        VecLoader::new(vec![1, 20, 30, 5, 6, 7, 40, 2, 3])
+            .filter(|n: &usize| !(5..8).contains(n))
+            .bucket_batch(2, |n: &usize| *n, Buckets::Boundaries(vec![10]))
+    };
+    let mut batches = batcher();
+    batches.reset(Epoch::default());
+    assert_eq!(batches.process(vec![(); 3]), vec![vec![20, 30]]);
+
+    // A chunk filtered away doesn't flush the buckets
+    assert_eq!(batches.process(vec![(); 3]), Vec::<Vec<usize>>::new());
+    assert_eq!(batches.size_hint(SizeHint::exact(0)).lower, 1);
+
+    // The leftovers are flushed once the source runs out
+    assert_eq!(
+        batches.process(vec![(); 3]),
+        vec![vec![1, 2], vec![3], vec![40]]
+    );
+    assert!(batches.size_hint(SizeHint::exact(0)).is_empty());
+
+    // Resetting drops anything still waiting
+    let mut batches = batcher();
+    batches.reset(Epoch::default());
+    batches.process(vec![(); 3]);
+    batches.reset(Epoch::default());
+    assert_eq!(batches.run(3).iter().map(Vec::len).sum::<usize>(), 6);
+
+    // Bucket counts learn their boundaries from the first examples
+    let mut batches = VecLoader::new((0..100).collect::<Vec<usize>>()).bucket_batch(
+        8,
+        |n: &usize| *n,
+        Buckets::Count(4),
+    );
+    batches.reset(Epoch::default());
+    let batches = batches.run(50);
+    assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), 100);
+    for batch in batches {
+        let (min, max) = (batch.iter().min().unwrap(), batch.iter().max().unwrap());
+        assert!(max - min < 50);
+    }
+
+    // With serde state, a checkpoint taken with examples waiting resumes to the same batches, keeping the learned boundaries
+    let batcher = || {
+        VecLoader::new((0..60).map(|i| i * 37 % 60).collect::<Vec<usize>>())
+            .bucket_batch(4, |n: &usize| *n, Buckets::Count(3))
+            .with_serde_state()
+    };
+    let mut original = batcher();
+    original.reset(Epoch::default());
+    let mut delivered = original.process(vec![(); 10]);
+    delivered.extend(original.process(vec![(); 7]));
+    let delivered = delivered.iter().map(Vec::len).sum::<usize>();
+    assert!(delivered < 17);
+    let state = original.state_dict();
+    let rest = original.run(5);
+    let mut restored = batcher();
+    restored.reset(Epoch::default());
+    restored.load_state_dict(&state);
+    assert_eq!(restored.run(5), rest);
+    assert_eq!(rest.iter().map(Vec::len).sum::<usize>(), 60 - delivered);
+}
+
+#[test]
+fn test_token_budget_batch() {
+    // Padded cost packs until the batch times its longest example would go over, keeping the last batch open across calls
+    let mut batcher = VecLoader::new(vec![2, 2, 4, 1, 9, 3]).token_budget_batch(8, |n: &usize| *n);
+    batcher.reset(Epoch::default());
+    assert_eq!(batcher.process(vec![(); 3]), vec![vec![2, 2]]);
+    // 2 examples per batch so far, with 4 examples left
+    assert_eq!(batcher.data_remaining(0), 2);
+    assert_eq!(
+        batcher.process(vec![(); 3]),
+        vec![vec![4, 1], vec![9], vec![3]]
+    );
+    assert!(batcher.size_hint(SizeHint::exact(0)).is_empty());
+
+    // Summed cost and an item cap
+    let mut batcher = VecLoader::new(vec![1, 1, 1, 1, 4, 4])
+        .token_budget_batch(8, |n: &usize| *n)
+        .cost(TokenCost::Sum)
+        .max_items(3);
+    assert_eq!(
+        batcher.size_hint(SizeHint::unknown()),
+        SizeHint {
+            lower: 2,
+            upper: Some(6)
+        }
+    );
+    batcher.reset(Epoch::default());
+    assert_eq!(batcher.run(2), vec![vec![1, 1, 1], vec![1, 4], vec![4]]);
+
+    // Sorting within a lookahead window groups similar lengths, and windows fill across calls
+    let mut batcher = VecLoader::new(vec![4, 1, 4, 1, 2])
+        .token_budget_batch(8, |n: &usize| *n)
+        .lookahead(4);
+    batcher.reset(Epoch::default());
+    assert_eq!(batcher.process(vec![(); 2]), Vec::<Vec<usize>>::new());
+    assert_eq!(
+        batcher.process(vec![(); 3]),
+        vec![vec![1, 1], vec![4, 4], vec![2]]
+    );
+
+    // With serde state, a checkpoint taken while a batch is open resumes to the same batches
+    let batcher = || {
+        VecLoader::new((0..40).map(|i| i * 7 % 5 + 1).collect::<Vec<usize>>())
+            .token_budget_batch(12, |n: &usize| *n)
+            .lookahead(3)
+            .with_serde_state()
+    };
+    let mut original = batcher();
+    original.reset(Epoch::default());
+    let mut delivered = original.process(vec![(); 7]);
+    let state = original.state_dict();
+    let rest = original.run(4);
+    let mut restored = batcher();
+    restored.reset(Epoch::default());
+    restored.load_state_dict(&state);
+    assert_eq!(restored.run(4), rest);
+    delivered.extend(rest);
+    assert_eq!(delivered.iter().map(Vec::len).sum::<usize>(), 40);
+}
+
+#[test]
+fn test_shuffle_buffer() {
+    let numbers = || VecLoader::new((0..100).collect::<Vec<usize>>());
+    let run = |seed| {
+        let mut shuffle = numbers().shuffle_buffer(16);
+        shuffle.reset(Epoch::new(0, seed));
+        let mut output = vec![];
+        for _ in 0..10 {
+            // Every call fills up with examples from the source
+            let mut chunk = shuffle.process(vec![(); 10]);
+            assert_eq!(chunk.len(), 10);
+            output.append(&mut chunk);
+        }
+        assert!(shuffle.size_hint(SizeHint::exact(0)).is_empty());
+        output
+    };
+
+    // Every example comes out once, mixed across calls
+    let output = run(1);
+    let mut sorted = output.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    assert!(output[..10].iter().any(|i| *i >= 10));
+
+    // The same seed shuffles the same way
+    assert_eq!(output, run(1));
+    assert_ne!(output, run(2));
+
+    // Chunks filtered away don't drain the buffer early
+    let mut filtered = numbers()
+        .filter(|i: &usize| !(20..60).contains(i))
+        .shuffle_buffer(16);
+    filtered.reset(Epoch::default());
+    let mut output = filtered.process(vec![(); 30]);
+    assert_eq!(output.len(), 30);
+    assert!(output.iter().any(|i| *i >= 60));
+    output.append(&mut filtered.run(10));
+    output.sort();
+    assert_eq!(output, (0..20).chain(60..100).collect::<Vec<_>>());
+
+    // With serde state, restoring keeps the buffered examples
+    let mut shuffle = numbers().shuffle_buffer(16).with_serde_state();
+    shuffle.reset(Epoch::new(0, 3));
+    shuffle.process(vec![(); 30]);
+    let state = shuffle.state_dict();
+    let rest = shuffle.run(10);
+    let mut restored = numbers().shuffle_buffer(16).with_serde_state();
+    restored.reset(Epoch::new(0, 3));
+    restored.load_state_dict(&state);
+    assert_eq!(restored.run(10), rest);
+}
+
+#[test]
+fn test_global_sort() {
+    let spill_dir = std::env::temp_dir().join("dataflow-test-global-sort");
+    let pipeline = |max_in_memory| {
+        VecLoader::new((0..1000).rev().collect::<Vec<usize>>())
+            .global_sort(|a: &usize, b: &usize| (a % 10).cmp(&(b % 10)))
+            .max_in_memory(max_in_memory)
+            .max_fan_in(3)
+            .spill_dir(&spill_dir)
+    };
+
+    // Sorted across calls, keeping equal examples in the order they came in. Spilled runs are merged 3 at a time
+    let mut expected = (0..1000).rev().collect::<Vec<usize>>();
+    expected.sort_by_key(|i| i % 10);
+    for max_in_memory in [10_000, 64, 10] {
+        let mut sorted = pipeline(max_in_memory);
+        sorted.reset(Epoch::default());
+        assert_eq!(sorted.size_hint(SizeHint::unknown()), SizeHint::exact(1000));
+        assert_eq!(sorted.process(vec![(); 100]), expected[..100]);
+        assert_eq!(sorted.size_hint(SizeHint::unknown()), SizeHint::exact(900));
+        assert_eq!(sorted.run(300), expected[100..]);
+    }
+    // Runs are cleaned up once the merge is done with
+    assert_eq!(std::fs::read_dir(&spill_dir).unwrap().count(), 0);
+
+    // Restoring sorts again and skips what was delivered
+    let mut sorted = pipeline(64);
+    sorted.reset(Epoch::default());
+    sorted.process(vec![(); 250]);
+    let state = sorted.state_dict();
+    let mut restored = pipeline(64);
+    restored.reset(Epoch::default());
+    restored.load_state_dict(&state);
+    assert_eq!(restored.process(vec![(); 10]), expected[250..260]);
+}
+
+#[test]
+fn test_streaming_mapreduce() {
+    let spill_dir = std::env::temp_dir().join("dataflow-test-mapreduce");
+    let words = |i: usize| {
+        [
+            format!("w{}", i % 7),
+            format!("w{}", i % 13),
+            "common".to_string(),
+        ]
+    };
+    let keep = |i: &usize| !(100..200).contains(i);
+    let mut expected = std::collections::BTreeMap::<String, usize>::new();
+    for word in (0..500).filter(keep).flat_map(words) {
+        *expected.entry(word).or_default() += 1;
+    }
+    let expected = expected.into_iter().collect::<Vec<_>>();
+
+    let counter = || {
+        MapReduce::new(
+            move |i: usize| words(i).into_iter().map(|w| (w, 1)).collect::<Vec<_>>(),
+            |(word, counts): (String, Vec<usize>)| vec![(word, counts.into_iter().sum::<usize>())],
+        )
+        .streaming(VecLoader::new((0..500).collect::<Vec<usize>>()).filter(keep))
+        .partitions(3)
+        .reducers(2)
+        .max_in_memory(10)
+        .spill_dir(&spill_dir)
+    };
+
+    // Keys spanning chunks and spills are reduced together, with or without a combiner.
+    // Chunks the filter empties don't end the map early, and the output streams out in chunks
+    for combine in [false, true] {
+        let mut counter = counter();
+        if combine {
+            counter = counter.combiner(|a, b| a + b);
+        }
+        counter.reset(Epoch::default());
+        let mut counts = counter.process(vec![(); 4]);
+        assert_eq!(counts.len(), 4);
+        // Only outputs already reduced are counted
+        let hint = counter.size_hint(SizeHint::unknown());
+        assert_eq!(hint.upper, None);
+        assert!(hint.lower <= expected.len() - 4);
+        assert_eq!(counter.data_remaining(0), hint.lower);
+        counts.append(&mut counter.run(4));
+        counts.sort();
+        assert_eq!(counts, expected);
+    }
+    assert_eq!(std::fs::read_dir(&spill_dir).unwrap().count(), 0);
+
+    // Restoring maps and reduces again, skipping what was delivered
+    let mut node = counter();
+    node.reset(Epoch::default());
+    node.process(vec![(); 5]);
+    let state = node.state_dict();
+    let rest = node.run(5);
+    assert_eq!(rest.len(), expected.len() - 5);
+    let mut restored = counter();
+    restored.reset(Epoch::default());
+    restored.load_state_dict(&state);
+    assert_eq!(restored.run(5), rest);
+}
+
+#[test]
+fn test_stateful() {
+    // Counts how many examples went through, starting over every epoch
+    let counter = Stateful::new(0usize, |input: Vec<i32>, seen: &mut usize| {
+        *seen += input.len();
+        input
+    })
+    .on_reset(|seen| *seen = 0)
+    .with_serde_state();
+    let seen = counter.state_handle();
+    let mut pipeline = VecLoader::new((0..10).collect::<Vec<i32>>()).chain(counter);
+    pipeline.reset(Epoch::default());
+    pipeline.process(vec![(); 4]);
+    assert_eq!(*seen.lock().unwrap(), 4);
+
+    // The state is snapshotted with serde
+    let state = pipeline.state_dict();
+    pipeline.process(vec![(); 4]);
+    assert_eq!(*seen.lock().unwrap(), 8);
+    pipeline.reset(Epoch::default());
+    assert_eq!(*seen.lock().unwrap(), 0);
+    pipeline.load_state_dict(&state);
+    assert_eq!(*seen.lock().unwrap(), 4);
+
+    // The handle can change the state from outside
+    *seen.lock().unwrap() = 100;
+    pipeline.process(vec![(); 1]);
+    assert_eq!(*seen.lock().unwrap(), 101);
+}
+
+#[test]
+fn test_dedup() {
+    // The seen set spans calls, and is cleared on reset
+    let mut dedup = Dedup::new(|n: &i32| n % 10).with_serde_state();
+    assert_eq!(dedup.process(vec![1, 2, 11]), vec![1, 2]);
+    assert_eq!(dedup.process(vec![21, 3]), vec![3]);
+    assert_eq!(
+        dedup.size_hint(SizeHint::exact(5)),
+        SizeHint {
+            lower: 0,
+            upper: Some(5)
+        }
+    );
+    // The seen keys are checkpointed when asked to, and keys don't need serde otherwise
+    let state = dedup.state_dict();
+    let mut restored = Dedup::new(|n: &i32| n % 10).with_serde_state();
+    restored.load_state_dict(&state);
+    assert_eq!(restored.process(vec![12, 4]), vec![4]);
+    struct Opaque(i32);
+    impl PartialEq for Opaque {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+    impl Eq for Opaque {}
+    impl std::hash::Hash for Opaque {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.0.hash(state)
+        }
+    }
+    let mut opaque = Dedup::new(|n: &i32| Opaque(n % 10));
+    assert_eq!(opaque.process(vec![1, 11]), vec![1]);
+    assert_eq!(opaque.state_dict(), NodeState::Empty);
+    dedup.reset(Epoch::default());
+    assert_eq!(dedup.process(vec![21]), vec![21]);
+
+    // Near duplicates are dropped and recorded in clusters
+    let base = (0..40).map(|i| format!("w{i}")).collect::<Vec<_>>();
+    let mut edited = base.clone();
+    edited[20] = "changed".to_string();
+    let mut other = base.clone();
+    other.reverse();
+    let texts = vec![
+        base.join(" "),
+        other.join(" "),
+        edited.join(" "),
+        base.join(" "),
+    ];
+    let mut dedup = NearDedup::new(|s: &String| s.as_str())
+        .shingle_size(3)
+        .record_clusters();
+    let clusters = dedup.clusters();
+    let kept = dedup.process(texts.clone());
+    assert_eq!(kept, vec![texts[0].clone(), texts[1].clone()]);
+    assert_eq!(
+        *clusters.lock().unwrap(),
+        vec![DuplicateCluster {
+            kept: 0,
+            duplicates: vec![2, 3]
+        }]
+    );
+
+    // Across calls too, counting positions through the epoch
+    let state = dedup.state_dict();
+    assert!(dedup.process(vec![edited.join(" ")]).is_empty());
+    assert_eq!(clusters.lock().unwrap()[0].duplicates, vec![2, 3, 4]);
+
+    // Restoring keeps the kept signatures and the clusters recorded so far
+    let mut restored = NearDedup::new(|s: &String| s.as_str())
+        .shingle_size(3)
+        .record_clusters();
+    let restored_clusters = restored.clusters();
+    restored.load_state_dict(&state);
+    assert!(restored.process(vec![edited.join(" ")]).is_empty());
+    assert_eq!(*restored_clusters.lock().unwrap(), *clusters.lock().unwrap());
+    dedup.reset(Epoch::default());
+    assert!(clusters.lock().unwrap().is_empty());
+    assert_eq!(dedup.process(texts).len(), 2);
+}
+
+#[test]
+fn test_take_skip_repeat() {
+    // Take and skip keep the remaining data accurate
+    let mut pipeline = VecLoader::new((0..100).collect::<Vec<usize>>())
+        .chain(Skip::new(10))
+        .chain(Take::new(25));
+    pipeline.reset(Epoch::default());
+    assert_eq!(pipeline.data_remaining(0), 25);
+    assert_eq!(pipeline.size_hint(SizeHint::unknown()), SizeHint::exact(25));
+    assert_eq!(pipeline.process(vec![(); 15]).len(), 5);
+    assert_eq!(pipeline.data_remaining(0), 20);
+    assert_eq!(pipeline.run(8).len(), 20);
+
+    // A checkpoint from a longer take or skip clamps to the new count
+    let mut take = Take::<usize>::new(5);
+    take.load_state_dict(&NodeState::Index(8));
+    assert_eq!(take.data_remaining(10), 0);
+    assert!(take.process(vec![1, 2]).is_empty());
+    let mut skip = Skip::<usize>::new(5);
+    skip.load_state_dict(&NodeState::Index(8));
+    assert_eq!(skip.data_remaining(10), 10);
+    assert_eq!(skip.process(vec![1, 2]), vec![1, 2]);
+
+    // Repeat runs the source several times, with a new order each pass
+    let source = || VecLoader::new((0..10).collect::<Vec<usize>>()).chain(Shuffle::default());
+    let mut pipeline = source().repeat(3);
+    pipeline.reset(Epoch::new(0, 1));
+    assert_eq!(pipeline.size_hint(SizeHint::unknown()), SizeHint::exact(30));
+    let first = pipeline.process(vec![(); 12]);
+    assert_eq!(pipeline.data_remaining(0), 18);
+    let state = pipeline.state_dict();
+    let rest = pipeline.process(vec![(); 100]);
+    assert_eq!(rest.len(), 18);
+    assert!(pipeline.size_hint(SizeHint::unknown()).is_empty());
+    let mut passes = first.iter().chain(&rest).copied().collect::<Vec<_>>();
+    assert_ne!(passes[..10], passes[10..20]);
+    passes.sort();
+    assert_eq!(passes, (0..30).map(|i| i / 3).collect::<Vec<_>>());
+
+    // Restoring picks up mid pass
+    let mut restored = source().repeat(3);
+    restored.reset(Epoch::new(0, 1));
+    restored.load_state_dict(&state);
+    assert_eq!(restored.process(vec![(); 100]), rest);
+
+    // Cycle never runs out, unless the source is empty
+    let mut pipeline = VecLoader::new(vec![1, 2, 3]).cycle();
+    pipeline.reset(Epoch::default());
+    assert_eq!(pipeline.process(vec![(); 10]).len(), 10);
+    assert_eq!(pipeline.size_hint(SizeHint::unknown()).upper, None);
+    let mut pipeline = VecLoader::new(Vec::<usize>::new()).cycle();
+    pipeline.reset(Epoch::default());
+    assert!(pipeline.size_hint(SizeHint::unknown()).is_empty());
+    assert!(pipeline.process(vec![(); 10]).is_empty());
+}
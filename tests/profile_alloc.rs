@@ -0,0 +1,31 @@
+//! Runs on its own, since the counting allocator is global to the test binary
+use dataflow::pipeline::*;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn add_ten(nums: Vec<i32>) -> Vec<i32> {
+    nums.into_iter().map(|n| n + 10).collect()
+}
+
+#[test]
+fn test_profiled_pipeline() {
+    let load = Profiled::new(VecLoader::new((0..100).collect::<Vec<i32>>()), "load");
+    let pipeline = load
+        .chain(add_ten.profile("add_ten"))
+        .chain(Batch::new(10).profile("batch"));
+    let profiles = Node::profiles(&pipeline);
+    let outputs = pipeline.run(50);
+    assert_eq!(outputs.len(), 10);
+
+    let stats = profiles.iter().map(Profile::stats).collect::<Vec<_>>();
+    assert_eq!(
+        stats.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        ["load", "add_ten", "batch"]
+    );
+    assert!(stats.iter().all(|s| s.calls == 2));
+    assert_eq!((stats[0].items_in, stats[0].items_out), (100, 100));
+    assert_eq!((stats[2].items_in, stats[2].items_out), (100, 10));
+    // Batching allocates the new batches
+    assert!(stats[2].allocations >= 10);
+}